@@ -1,21 +1,47 @@
-use ethereum_types::H256;
-use std::path::PathBuf;
+use ethereum_types::{H160, H256, H512};
+use ethkey::math;
+use std::{fmt, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
+use crate::storage::Cid;
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "secret-store-cli", about = "secret-store sample")]
+pub struct Opts {
+    #[structopt(
+        long = "profile",
+        global = true,
+        help = "Configuration profile to use (defaults to SECRET_STORE_PROFILE or default_profile)"
+    )]
+    pub profile: Option<String>,
+
+    #[structopt(subcommand)]
+    pub cmd: Args,
+}
+
+#[derive(StructOpt, Debug)]
 pub enum Args {
     #[structopt(name = "decrypt", about = "Decrypt document")]
     Decrypt {
         #[structopt(help = "Pass a dockey_id", required = true)]
         dockey_id: H256,
 
-        #[structopt(help = "Pass a document hash you want to decrypt", required = true)]
-        ipfs_uri: String,
+        #[structopt(
+            help = "Pass the location of the document you want to decrypt (IPFS hash by default)",
+            required = true
+        )]
+        location: String,
+
+        #[structopt(
+            long = "output",
+            short = "o",
+            help = "Write the document to this file instead of stdout, a directory restores the original file name"
+        )]
+        output: Option<PathBuf>,
     },
     #[structopt(
         name = "encrypt",
-        about = "Encrypt document and receive document key ID and IPFS hash"
+        about = "Encrypt document and receive document key ID and storage location"
     )]
     Encrypt {
         #[structopt(
@@ -23,6 +49,126 @@ pub enum Args {
             required = true
         )]
         file: PathBuf,
+
+        #[structopt(
+            long = "threshold",
+            default_value = "1",
+            help = "Server key threshold, any threshold + 1 nodes can recover the document key"
+        )]
+        threshold: usize,
+
+        #[structopt(
+            long = "server-generated",
+            help = "Let the cluster generate and store the document key in one session"
+        )]
+        server_generated: bool,
+
+        #[structopt(
+            long = "keep-ciphertext",
+            help = "Also write the encrypted document (the manifest when chunked) to this path, which must not exist yet"
+        )]
+        keep_ciphertext: Option<PathBuf>,
+
+        #[structopt(
+            long = "chunk-size",
+            help = "Larger documents are encrypted and stored in chunks of this many bytes, 1 MiB unless given"
+        )]
+        chunk_size: Option<usize>,
+
+        #[structopt(
+            long = "recipient",
+            help = "Also encrypt the document key to this public key, or the address of a keystore account, so it can decrypt without the cluster"
+        )]
+        recipients: Vec<Recipient>,
+    },
+    #[structopt(
+        name = "key-info",
+        about = "Show whether a dockey id is registered and its document key can be retrieved"
+    )]
+    KeyInfo {
+        #[structopt(help = "Pass a dockey_id", required = true)]
+        dockey_id: H256,
+    },
+    #[structopt(
+        name = "sign",
+        about = "Sign message hash with a server key in a threshold signing session"
+    )]
+    Sign {
+        #[structopt(help = "Pass a server key id", required = true)]
+        key_id: H256,
+
+        #[structopt(help = "Pass a message hash you want to sign", required = true)]
+        message_hash: H256,
+
+        #[structopt(
+            long = "scheme",
+            default_value = "schnorr",
+            help = "Signing scheme: schnorr or ecdsa"
+        )]
+        scheme: SigningScheme,
+    },
+    #[structopt(
+        name = "sign-message",
+        about = "Sign a message with the account as personal_sign does (EIP-191)"
+    )]
+    SignMessage {
+        #[structopt(help = "Pass the message you want to sign", required = true)]
+        message: String,
+    },
+    #[structopt(
+        name = "sign-typed-data",
+        about = "Sign EIP-712 typed data with the account"
+    )]
+    SignTypedData {
+        #[structopt(
+            help = "JSON file with types, primaryType, domain and message as for eth_signTypedData",
+            required = true
+        )]
+        file: PathBuf,
+    },
+    #[structopt(
+        name = "pin",
+        about = "Manage pins of encrypted documents on the IPFS node"
+    )]
+    Pin {
+        #[structopt(subcommand)]
+        cmd: PinCmd,
+    },
+    #[structopt(
+        name = "recover",
+        about = "Recover the address and public key that signed a hash"
+    )]
+    Recover {
+        #[structopt(
+            help = "65 byte signature in hex, as returned by secretstore_signRawHash or the sign-message commands",
+            required = true
+        )]
+        signature: String,
+
+        #[structopt(flatten)]
+        signed: SignedHash,
+    },
+    #[structopt(
+        name = "verify",
+        about = "Check that a hash was signed by an address, e.g. a signed dockey id"
+    )]
+    Verify {
+        #[structopt(
+            help = "65 byte signature in hex, as returned by secretstore_signRawHash or the sign-message commands",
+            required = true
+        )]
+        signature: String,
+
+        #[structopt(long = "address", required = true, help = "Expected signer")]
+        address: H160,
+
+        #[structopt(flatten)]
+        signed: SignedHash,
+    },
+    #[structopt(name = "admin", about = "Cluster administration")]
+    Admin {
+        #[structopt(subcommand)]
+        cmd: AdminCmd,
     },
     // #[structopt(name="vhalist", about="Get VHA List you've purchased")]
     // VhaList,
@@ -30,6 +176,158 @@ pub enum Args {
     Address,
 }
 
-pub fn parse() -> Args {
-    Args::from_args()
+/// What a signature was made over.
+#[derive(StructOpt, Debug)]
+pub struct SignedHash {
+    #[structopt(
+        long = "hash",
+        required_unless_one = &["file", "message", "typed-data"],
+        conflicts_with_all = &["file", "message", "typed-data"],
+        help = "Signed hash, e.g. a dockey id"
+    )]
+    pub hash: Option<H256>,
+
+    #[structopt(
+        long = "file",
+        conflicts_with_all = &["message", "typed-data"],
+        help = "File whose sha256 was signed, as for the dockey id of an encrypted document"
+    )]
+    pub file: Option<PathBuf>,
+
+    #[structopt(
+        long = "message",
+        conflicts_with = "typed-data",
+        help = "Message signed with sign-message or personal_sign (EIP-191)"
+    )]
+    pub message: Option<String>,
+
+    #[structopt(
+        long = "typed-data",
+        help = "JSON file of EIP-712 typed data signed with sign-typed-data"
+    )]
+    pub typed_data: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum AdminCmd {
+    #[structopt(
+        name = "servers-set-change",
+        about = "Sign old and new servers set hashes and start a servers set change session"
+    )]
+    ServersSetChange {
+        #[structopt(
+            long = "old",
+            help = "Public keys of the current nodes (defaults to the profile `servers`)"
+        )]
+        old: Vec<H512>,
+
+        #[structopt(long = "new", required = true, help = "Public keys of the new nodes")]
+        new: Vec<H512>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PinCmd {
+    #[structopt(name = "add", about = "Pin a document on the IPFS node")]
+    Add {
+        #[structopt(help = "CID of the document", required = true)]
+        cid: Cid,
+    },
+    #[structopt(
+        name = "rm",
+        about = "Unpin a document, refusing documents encrypted with this profile"
+    )]
+    Rm {
+        #[structopt(help = "CID of the document", required = true)]
+        cid: Cid,
+
+        #[structopt(long = "force", help = "Unpin even if a recorded dockey id uses it")]
+        force: bool,
+    },
+    #[structopt(name = "ls", about = "List pins with the dockey ids they hold")]
+    Ls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigningScheme {
+    Schnorr,
+    Ecdsa,
+}
+
+impl FromStr for SigningScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schnorr" => Ok(SigningScheme::Schnorr),
+            "ecdsa" => Ok(SigningScheme::Ecdsa),
+            _ => Err(format!("unknown signing scheme: {}", s)),
+        }
+    }
+}
+
+/// Account a document key is wrapped for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recipient {
+    Public(H512),
+    /// Account of the profile keystore, its public key is read with its password.
+    Address(H160),
+}
+
+impl FromStr for Recipient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches("0x");
+        let recipient = match hex.len() {
+            40 => hex.parse().ok().map(Recipient::Address),
+            128 => hex.parse().ok().map(Recipient::Public),
+            _ => None,
+        };
+        // keys are encrypted to the point, one off the curve would fail only after the upload
+        if let Some(Recipient::Public(ref public)) = recipient {
+            if !math::public_is_valid(public) {
+                return Err(format!("not a point on secp256k1: {}", s));
+            }
+        }
+        recipient.ok_or_else(|| format!("neither a public key nor an address: {}", s))
+    }
+}
+
+impl fmt::Display for SigningScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SigningScheme::Schnorr => write!(f, "schnorr"),
+            SigningScheme::Ecdsa => write!(f, "ecdsa"),
+        }
+    }
+}
+
+pub fn parse() -> Opts {
+    Opts::from_args()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recipient;
+    use ethkey::{Generator, Random};
+
+    #[test]
+    fn parses_recipients() {
+        let public = *Random.generate().unwrap().public();
+        assert_eq!(
+            format!("0x{:x}", public).parse(),
+            Ok(Recipient::Public(public))
+        );
+        assert_eq!(
+            "0x00a329c0648769a73afac7f9381e08fb43dbea72".parse(),
+            Ok(Recipient::Address(
+                "00a329c0648769a73afac7f9381e08fb43dbea72".parse().unwrap()
+            ))
+        );
+        // 64 bytes that are not a public key
+        let invalid = "0".repeat(128).parse::<Recipient>().unwrap_err();
+        assert!(invalid.contains("secp256k1"));
+        assert!("0x1234".parse::<Recipient>().is_err());
+    }
 }
@@ -0,0 +1,42 @@
+extern crate secret_store_cli;
+
+use std::net::SocketAddr;
+use std::process;
+
+use hyper::rt;
+use secret_store_cli::mock;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "secret-store-mock",
+    about = "Local stand-in for a Secret Store cluster HTTP API"
+)]
+struct Opts {
+    #[structopt(
+        long = "listen",
+        default_value = "127.0.0.1:8000",
+        help = "Address to serve the HTTP API on"
+    )]
+    listen: SocketAddr,
+
+    #[structopt(
+        long = "nodes",
+        default_value = "3",
+        help = "Number of simulated nodes"
+    )]
+    nodes: usize,
+}
+
+fn main() {
+    let opts = Opts::from_args();
+    let (addr, server) = mock::serve(&opts.listen, opts.nodes).unwrap_or_else(|e| {
+        eprintln!("Could not listen on {}: {}", opts.listen, e);
+        process::exit(1);
+    });
+    println!(
+        "mock secret store with {} nodes listening on http://{}",
+        opts.nodes, addr
+    );
+    rt::run(server);
+}
@@ -0,0 +1,565 @@
+//! Typed client for the Secret Store HTTP API.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use ethereum_types::{H256, H512};
+use ethkey::Signature;
+use reqwest::{Client, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use crate::bytes::Bytes;
+use crate::document_key::DecryptedDocumentKey;
+
+/// Errors returned by a Secret Store node, or while talking to it.
+#[derive(Debug)]
+pub enum Error {
+    /// Node url could not be used to build a request.
+    InvalidUrl(String),
+    /// Request could not be sent or its body could not be read.
+    Http(reqwest::Error),
+    /// Node answered with a body we could not decode.
+    InvalidResponse(String),
+    /// Request signature or parameters were rejected.
+    BadRequest(String),
+    /// Requester is not allowed to access the key.
+    AccessDenied(String),
+    /// No server key is registered for the given id.
+    ServerKeyNotFound(String),
+    /// Server key exists, but no document key was stored for it.
+    DocumentKeyNotFound(String),
+    /// Server key with the given id has already been generated.
+    ServerKeyAlreadyGenerated(String),
+    /// Document key for the given id has already been stored.
+    DocumentKeyAlreadyStored(String),
+    /// Cluster has not enough nodes to serve the requested threshold.
+    NotEnoughNodes(String),
+    /// Cluster nodes could not agree on the session.
+    ConsensusUnreachable(String),
+    /// Any other error reported by the node.
+    Server(StatusCode, String),
+    /// Every node failed with a transport or server error.
+    NodesFailed(Vec<NodeFailure>),
+}
+
+/// Last error of a node that could not serve a request.
+#[derive(Debug)]
+pub struct NodeFailure {
+    pub node: String,
+    pub attempts: usize,
+    pub error: Error,
+}
+
+impl Error {
+    /// Maps an unsuccessful node response onto an error variant.
+    ///
+    /// Secret Store returns its errors as a JSON encoded string, e.g. `"Access dened"`.
+    pub fn from_response(status: StatusCode, body: &str) -> Self {
+        let message = serde_json::from_str::<String>(body).unwrap_or_else(|_| body.trim().into());
+        let lowercase = message.to_lowercase();
+        match status {
+            StatusCode::FORBIDDEN => Error::AccessDenied(message),
+            StatusCode::NOT_FOUND if lowercase.contains("document key") => {
+                Error::DocumentKeyNotFound(message)
+            }
+            StatusCode::NOT_FOUND => Error::ServerKeyNotFound(message),
+            StatusCode::BAD_REQUEST => Error::BadRequest(message),
+            _ if lowercase.contains("server key with this id is already generated") => {
+                Error::ServerKeyAlreadyGenerated(message)
+            }
+            _ if lowercase.contains("document key with this id is already stored") => {
+                Error::DocumentKeyAlreadyStored(message)
+            }
+            _ if lowercase.contains("not enough nodes") => Error::NotEnoughNodes(message),
+            _ if lowercase.contains("consensus unreachable") => {
+                Error::ConsensusUnreachable(message)
+            }
+            _ => Error::Server(status, message),
+        }
+    }
+
+    /// Whether another attempt, possibly on another node, could succeed.
+    ///
+    /// Requests that are not `idempotent`, e.g. key generation, are only retried when they never
+    /// reached the node. A node could have started the session before timing out, so sending
+    /// them again would start a second one.
+    pub fn is_retryable(&self, idempotent: bool) -> bool {
+        match *self {
+            Error::Http(ref err) => idempotent || is_connect_error(err),
+            Error::Server(status, _) => idempotent && status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidUrl(ref msg) => write!(f, "invalid secret store url: {}", msg),
+            Error::Http(ref err) => write!(f, "secret store request failed: {}", err),
+            Error::InvalidResponse(ref msg) => write!(f, "invalid secret store response: {}", msg),
+            Error::BadRequest(ref msg) => write!(f, "bad request: {}", msg),
+            Error::AccessDenied(ref msg) => write!(f, "access denied: {}", msg),
+            Error::ServerKeyNotFound(ref msg) => write!(f, "server key not found: {}", msg),
+            Error::DocumentKeyNotFound(ref msg) => write!(f, "document key not found: {}", msg),
+            Error::ServerKeyAlreadyGenerated(ref msg) => {
+                write!(f, "server key already generated: {}", msg)
+            }
+            Error::DocumentKeyAlreadyStored(ref msg) => {
+                write!(f, "document key already stored: {}", msg)
+            }
+            Error::NotEnoughNodes(ref msg) => write!(f, "not enough nodes: {}", msg),
+            Error::ConsensusUnreachable(ref msg) => write!(f, "consensus unreachable: {}", msg),
+            Error::Server(status, ref msg) => write!(f, "secret store error ({}): {}", status, msg),
+            Error::NodesFailed(ref failures) => {
+                write!(f, "all secret store nodes failed")?;
+                for failure in failures {
+                    write!(
+                        f,
+                        "\n  {} ({} attempts): {}",
+                        failure.node, failure.attempts, failure.error
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Connection could not be established, so the request was never sent.
+fn is_connect_error(err: &reqwest::Error) -> bool {
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<hyper::Error>())
+        .map_or(false, hyper::Error::is_connect)
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+/// Longest delay between two attempts, however many retries are configured.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Timeouts and retry schedule applied to every node request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestPolicy {
+    pub connect_timeout: Duration,
+    /// Time allowed for the whole request, including reading the response.
+    pub read_timeout: Duration,
+    /// Retries on the same node before failing over to the next one.
+    pub retries: usize,
+    /// Delay before the first retry, doubled on every following one.
+    pub backoff: Duration,
+}
+
+impl RequestPolicy {
+    /// Delay before retry number `attempt + 1`, capped at `MAX_BACKOFF`.
+    fn backoff(&self, attempt: usize) -> Duration {
+        2u32.checked_pow(attempt as u32)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+    }
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        RequestPolicy {
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            retries: 2,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Client for a Secret Store cluster.
+///
+/// Any node can serve a request, nodes are tried in order until one answers. Transport and
+/// server errors of `GET` requests are retried, requests that start a session only when the
+/// node could not be reached. Errors about the request itself are returned right away.
+pub struct SecretStoreHttpClient {
+    nodes: Vec<Url>,
+    api_key: Option<String>,
+    policy: RequestPolicy,
+    client: Client,
+}
+
+impl SecretStoreHttpClient {
+    pub fn new(url: &str, api_key: Option<String>) -> Result<Self, Error> {
+        Self::with_nodes(&[url], api_key, RequestPolicy::default())
+    }
+
+    pub fn with_nodes<S: AsRef<str>>(
+        urls: &[S],
+        api_key: Option<String>,
+        policy: RequestPolicy,
+    ) -> Result<Self, Error> {
+        if urls.is_empty() {
+            return Err(Error::InvalidUrl("no secret store nodes configured".into()));
+        }
+        let nodes = urls
+            .iter()
+            .map(|url| {
+                let url = url.as_ref();
+                let node =
+                    Url::parse(url).map_err(|e| Error::InvalidUrl(format!("{}: {}", url, e)))?;
+                if node.cannot_be_a_base() {
+                    return Err(Error::InvalidUrl(url.into()));
+                }
+                Ok(node)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let client = Client::builder()
+            .connect_timeout(policy.connect_timeout)
+            .timeout(policy.read_timeout)
+            .build()?;
+        Ok(SecretStoreHttpClient {
+            nodes,
+            api_key,
+            policy,
+            client,
+        })
+    }
+
+    /// Generates a server key, returning its public portion.
+    pub fn generate_server_key(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+        threshold: usize,
+    ) -> Result<H512, Error> {
+        let path = [
+            "shadow".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+            threshold.to_string(),
+        ];
+        decode(&self.request(Method::POST, &path, None)?)
+    }
+
+    /// Generates both server and document key on the cluster, returning the document key
+    /// encrypted with the requester public.
+    pub fn generate_document_key(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+        threshold: usize,
+    ) -> Result<Bytes, Error> {
+        let path = [
+            hex_key_id(key_id),
+            hex_signature(signature),
+            threshold.to_string(),
+        ];
+        decode(&self.request(Method::POST, &path, None)?)
+    }
+
+    /// Stores an externally generated document key.
+    pub fn store_document_key(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+        common_point: &H512,
+        encrypted_point: &H512,
+    ) -> Result<(), Error> {
+        let path = [
+            "shadow".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+            hex::encode(common_point.as_bytes()),
+            hex::encode(encrypted_point.as_bytes()),
+        ];
+        self.request(Method::POST, &path, None).map(|_| ())
+    }
+
+    /// Retrieves the public portion of a previously generated server key.
+    pub fn retrieve_server_key(&self, key_id: &H256, signature: &Signature) -> Result<H512, Error> {
+        let path = [
+            "server".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+        ];
+        decode(&self.request(Method::GET, &path, None)?)
+    }
+
+    /// Retrieves the document key encrypted with the requester public.
+    pub fn retrieve_document_key(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+    ) -> Result<Bytes, Error> {
+        let path = [hex_key_id(key_id), hex_signature(signature)];
+        decode(&self.request(Method::GET, &path, None)?)
+    }
+
+    /// Retrieves the document key in shadow form, to be decrypted by the requester.
+    pub fn retrieve_document_key_shadow(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+    ) -> Result<DecryptedDocumentKey, Error> {
+        let path = [
+            "shadow".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+        ];
+        decode(&self.request(Method::GET, &path, None)?)
+    }
+
+    /// Runs a Schnorr signing session, returning the signature encrypted with the
+    /// requester public.
+    pub fn schnorr_sign(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+        message_hash: &H256,
+    ) -> Result<Bytes, Error> {
+        let path = [
+            "schnorr".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+            hex::encode(message_hash.as_bytes()),
+        ];
+        decode(&self.request(Method::GET, &path, None)?)
+    }
+
+    /// Runs an ECDSA signing session, returning the signature encrypted with the
+    /// requester public.
+    pub fn ecdsa_sign(
+        &self,
+        key_id: &H256,
+        signature: &Signature,
+        message_hash: &H256,
+    ) -> Result<Bytes, Error> {
+        let path = [
+            "ecdsa".into(),
+            hex_key_id(key_id),
+            hex_signature(signature),
+            hex::encode(message_hash.as_bytes()),
+        ];
+        decode(&self.request(Method::GET, &path, None)?)
+    }
+
+    /// Starts a servers set change session.
+    ///
+    /// Both signatures are made by the administrator over `ordered_servers_keccak` of the
+    /// old and new servers set respectively.
+    pub fn change_servers_set(
+        &self,
+        old_set_signature: &Signature,
+        new_set_signature: &Signature,
+        new_servers_set: &BTreeSet<H512>,
+    ) -> Result<(), Error> {
+        let path = [
+            "admin".into(),
+            "servers_set_change".into(),
+            hex_signature(old_set_signature),
+            hex_signature(new_set_signature),
+        ];
+        let body = serde_json::to_string(new_servers_set)
+            .map_err(|e| Error::InvalidResponse(format!("{}", e)))?;
+        self.request(Method::POST, &path, Some(body)).map(|_| ())
+    }
+
+    fn url(&self, node: &Url, segments: &[String]) -> Result<Url, Error> {
+        let mut url = node.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::InvalidUrl(node.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        if let Some(ref api_key) = self.api_key {
+            url.query_pairs_mut().append_pair("apikey", api_key);
+        }
+        Ok(url)
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        segments: &[String],
+        body: Option<String>,
+    ) -> Result<String, Error> {
+        let idempotent = method == Method::GET;
+        let mut failures = Vec::new();
+        for node in &self.nodes {
+            let url = self.url(node, segments)?;
+            let mut attempt = 0;
+            let error = loop {
+                match self.send(method.clone(), &url, body.clone()) {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        if !e.is_retryable(idempotent) {
+                            return Err(e);
+                        }
+                        if attempt >= self.policy.retries {
+                            break e;
+                        }
+                        thread::sleep(self.policy.backoff(attempt));
+                        attempt += 1;
+                    }
+                }
+            };
+            failures.push(NodeFailure {
+                node: node.to_string(),
+                attempts: attempt + 1,
+                error,
+            });
+        }
+        Err(Error::NodesFailed(failures))
+    }
+
+    fn send(&self, method: Method, url: &Url, body: Option<String>) -> Result<String, Error> {
+        let mut request = self.client.request(method, url.as_str());
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+        let mut response = request.send()?;
+        let text = response.text()?;
+        if response.status().is_success() {
+            Ok(text)
+        } else {
+            Err(Error::from_response(response.status(), &text))
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| Error::InvalidResponse(format!("{}: {}", e, body)))
+}
+
+fn hex_key_id(key_id: &H256) -> String {
+    hex::encode(key_id.as_bytes())
+}
+
+fn hex_signature(signature: &Signature) -> String {
+    hex::encode(&signature[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethkey::Generator;
+
+    #[test]
+    fn builds_shadow_urls() {
+        let client =
+            SecretStoreHttpClient::new("http://localhost:8000", Some("key".into())).unwrap();
+        let url = client
+            .url(
+                &client.nodes[0],
+                &[
+                    "shadow".into(),
+                    hex_key_id(&H256::from_low_u64_be(1)),
+                    "1".into(),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:8000/shadow/0000000000000000000000000000000000000000000000000000000000000001/1?apikey=key"
+        );
+    }
+
+    #[test]
+    fn keeps_base_path_prefix() {
+        let client = SecretStoreHttpClient::new("http://localhost/ss/", None).unwrap();
+        let url = client
+            .url(&client.nodes[0], &["server".into(), "ab".into()])
+            .unwrap();
+        assert_eq!(url.as_str(), "http://localhost/ss/server/ab");
+    }
+
+    #[test]
+    fn maps_error_bodies() {
+        match Error::from_response(StatusCode::FORBIDDEN, r#""Access dened""#) {
+            Error::AccessDenied(msg) => assert_eq!(msg, "Access dened"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match Error::from_response(
+            StatusCode::NOT_FOUND,
+            r#""Document key with this ID is not found""#,
+        ) {
+            Error::DocumentKeyNotFound(_) => (),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match Error::from_response(
+            StatusCode::NOT_FOUND,
+            r#""Server key with this ID is not found""#,
+        ) {
+            Error::ServerKeyNotFound(_) => (),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match Error::from_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            r#""Server key with this ID is already generated""#,
+        ) {
+            Error::ServerKeyAlreadyGenerated(_) => (),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match Error::from_response(StatusCode::INTERNAL_SERVER_ERROR, "internal") {
+            Error::Server(status, msg) => {
+                assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+                assert_eq!(msg, "internal");
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    fn policy() -> RequestPolicy {
+        RequestPolicy {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(5),
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn retries_only_idempotent_requests() {
+        let server_error = Error::Server(StatusCode::BAD_GATEWAY, "".into());
+        assert!(server_error.is_retryable(true));
+        assert!(!server_error.is_retryable(false));
+        assert!(!Error::BadRequest("".into()).is_retryable(true));
+    }
+
+    #[test]
+    fn caps_backoff() {
+        let policy = policy();
+        assert_eq!(policy.backoff(0), Duration::from_millis(1));
+        assert_eq!(policy.backoff(3), Duration::from_millis(8));
+        assert_eq!(policy.backoff(31), MAX_BACKOFF);
+        assert_eq!(policy.backoff(64), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn fails_over_to_next_node() {
+        let nodes = ["http://127.0.0.1:1".to_string(), crate::mock::spawn(2)];
+        let client = SecretStoreHttpClient::with_nodes(&nodes, None, policy()).unwrap();
+        let requester = ethkey::Random.generate().unwrap();
+        let key_id = H256::random();
+        let signature = ethkey::sign(requester.secret(), &key_id).unwrap();
+        assert!(client.generate_server_key(&key_id, &signature, 1).is_ok());
+    }
+
+    #[test]
+    fn reports_every_failed_node() {
+        let nodes = ["http://127.0.0.1:1", "http://127.0.0.1:2"];
+        let client = SecretStoreHttpClient::with_nodes(&nodes, None, policy()).unwrap();
+        let key_id = H256::random();
+        let signature = Signature::default();
+        match client.retrieve_server_key(&key_id, &signature) {
+            Err(Error::NodesFailed(failures)) => {
+                assert_eq!(failures.len(), 2);
+                assert_eq!(failures[0].node, "http://127.0.0.1:1/");
+                assert_eq!(failures[1].attempts, 2);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -1,6 +1,6 @@
-use crate::dependency::Dependencies;
+use crate::{config::Profile, dependency::Dependencies};
 
-pub fn address() {
-    let deps = Dependencies::default();
+pub fn address(profile: &Profile) {
+    let deps = Dependencies::default(profile);
     println!("{:?}", deps.address);
 }
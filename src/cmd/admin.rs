@@ -0,0 +1,61 @@
+use std::{collections::BTreeSet, process};
+
+use ethereum_types::{H256, H512};
+use jsonrpc_core::IoHandler;
+use serde_json::json;
+
+use crate::{config::Profile, metadata::Metadata};
+
+pub fn servers_set_change(profile: &Profile, old: Vec<H512>, new: Vec<H512>) {
+    let old_servers_set: BTreeSet<H512> = if old.is_empty() {
+        profile.servers.clone()
+    } else {
+        old.into_iter().collect()
+    };
+    let new_servers_set: BTreeSet<H512> = new.into_iter().collect();
+    if old_servers_set.is_empty() || new_servers_set.is_empty() {
+        eprintln!(
+            "Both old and new servers set must be non-empty, pass --old or configure `servers`"
+        );
+        process::exit(1);
+    }
+
+    let (deps, password) = super::dependencies(profile);
+    let io = deps.default_client();
+    let cluster = super::cluster_client(profile);
+
+    let old_set_hash = servers_set_hash(&io, &old_servers_set);
+    let new_set_hash = servers_set_hash(&io, &new_servers_set);
+    let old_set_signature = super::sign_raw_hash(&deps, &password, &old_set_hash);
+    let new_set_signature = super::sign_raw_hash(&deps, &password, &new_set_hash);
+
+    println!("old_servers_set_hash: {:?}", old_set_hash);
+    println!("new_servers_set_hash: {:?}", new_set_hash);
+    cluster
+        .change_servers_set(&old_set_signature, &new_set_signature, &new_servers_set)
+        .unwrap_or_else(|e| {
+            eprintln!("Servers set change failed: {}", e);
+            process::exit(1);
+        });
+    println!("servers set change session started");
+}
+
+fn servers_set_hash(io: &IoHandler<Metadata>, servers_set: &BTreeSet<H512>) -> H256 {
+    let params = json!({
+        "jsonrpc": "2.0",
+        "method": "secretstore_serversSetHash",
+        "params": [
+            servers_set,
+        ],
+        "id": 1
+    });
+    let response = io
+        .handle_request_sync(&params.to_string())
+        .unwrap_or_default();
+    let hash = response.replace(r#"{"jsonrpc":"2.0","result":"0x"#, "");
+    let hash = hash.replace(r#"","id":1}"#, "");
+    hash.parse().unwrap_or_else(|e| {
+        eprintln!("Could not hash servers set: {} ({})", e, response);
+        process::exit(1);
+    })
+}
@@ -1,70 +1,280 @@
-use std::io::Result;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
 use ethereum_types::H256;
-use serde_json::{json, Value};
-use url::Url;
+use ethkey::{Password, Secret};
+use jsonrpc_core::Error;
+use serde_json::Value;
 
-use crate::dependency::Dependencies;
+use crate::{
+    config::Profile,
+    dependency::Dependencies,
+    document_key::{DecryptedDocumentKey, SecretBytes},
+    envelope::{self, Cipher, Document},
+    errors,
+    helpers::decrypt_with_shadow_coefficients,
+    records::Records,
+    storage, stream,
+    util::AtomicFile,
+};
 
-fn retrieve_document_keys(dockey_id: H256, signed_dockey_id: &str) -> Result<String> {
-    let base = Url::parse("http://localhost:8000/shadow/").unwrap();
-    let path = format!(
-        "{}{}{}{}",
-        &hex::encode(H256::as_bytes(&dockey_id)),
-        "/",
-        signed_dockey_id,
-        "?apikey=fa05a2e1-d323-4723-96b7-4d2695a61d3f"
-    );
-    let url = base.join(&path).unwrap();
-    let mut buf: Vec<u8> = vec![];
-    let mut res = reqwest::get(url.as_str()).unwrap();
-    res.copy_to(&mut buf).unwrap();
-    Ok(String::from_utf8(buf.to_vec()).unwrap())
+pub fn decrypt(profile: &Profile, dockey_id: H256, location: String, output: Option<PathBuf>) {
+    decrypt_as(profile, dockey_id, location, output, super::dependencies);
 }
 
-pub fn decrypt(dockey_id: H256, ipfs_uri: String) {
-    let deps = Dependencies::new();
-    let io = deps.default_client();
-
-    // TODO: should be replaced with 'select account and download encrypted file
-    let params = json!({
-        "jsonrpc": "2.0",
-        "method": "secretstore_signRawHash",
-        "params": [
-            &deps.address,
-            &deps.password,
-            &dockey_id,
-        ],
-        "id": 1
+/// Decrypts with the account `unlock` returns, asked for once the document was read.
+pub(super) fn decrypt_as<F>(
+    profile: &Profile,
+    dockey_id: H256,
+    location: String,
+    output: Option<PathBuf>,
+    unlock: F,
+) where
+    F: FnOnce(&Profile) -> (Dependencies, Password),
+{
+    let output = output.map(|path| output_path(profile, &dockey_id, path));
+    let storage = storage::open(profile).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let encrypted_document = storage.get(&location).unwrap_or_else(|e| {
+        eprintln!("Could not read encrypted document: {}", e);
+        process::exit(1);
     });
-    let signed_dockey_id = io.handle_request_sync(&params.to_string()).unwrap();
-    let signed_dockey_id = signed_dockey_id.replace(r#"{"jsonrpc":"2.0","result":"0x"#, "");
-    let signed_dockey_id = signed_dockey_id.replace(r#"","id":1}"#, "");
-    let decryption_keys: Value =
-        serde_json::from_str(&retrieve_document_keys(dockey_id, &signed_dockey_id).unwrap())
-            .unwrap();
-    let encryption_response = deps.cat_ipfs(&ipfs_uri).unwrap();
-    let encryption_response = encryption_response.replace("\"", "");
-    let params = json!({
-        "jsonrpc": "2.0",
-        "method": "secretstore_shadowDecrypt",
-        "params": [
-            &deps.address,
-            &deps.password,
-            &decryption_keys["decrypted_secret"],
-            &decryption_keys["common_point"],
-            &decryption_keys["decrypt_shadows"],
-            &encryption_response,
-        ],
-        "id": 1,
+    let document = envelope::parse(&encrypted_document).unwrap_or_else(|e| {
+        eprintln!("Could not read encrypted document: {}", e);
+        process::exit(1);
     });
-    let decrypted_document = io.handle_request_sync(&params.to_string()).unwrap();
-    let decrypted_document = decrypted_document.replace(r#"{"jsonrpc":"2.0","result":"0x"#, "");
-    let decrypted_document = decrypted_document.replace(r#"","id":1}"#, "");
-    let decoded_string = String::from_utf8(hex::decode(decrypted_document).unwrap()).unwrap();
-    let document = deps.decode_reader(&decoded_string).unwrap();
-    println!(
-        "{}",
-        String::from_utf8(document).expect("file could not convert to document")
-    );
+    let header = match document {
+        Document::Envelope(ref envelope) => Some(&envelope.header),
+        Document::Manifest(ref manifest) => Some(&manifest.header),
+        Document::Legacy(_) => None,
+    };
+    if let Some(header) = header {
+        if header.dockey_id != dockey_id {
+            eprintln!(
+                "Document at {} belongs to dockey_id {:?}",
+                location, header.dockey_id
+            );
+            process::exit(1);
+        }
+        // dockey id is the sha256 of the document, the hash of the header alone could have been
+        // chosen by whoever wrapped a key for us
+        if header.plaintext_hash != dockey_id {
+            eprintln!(
+                "Document at {} records plaintext hash {:?} instead of its dockey_id",
+                location, header.plaintext_hash
+            );
+            process::exit(1);
+        }
+    }
+    let legacy = header.is_none();
+    let threshold = header.map(|header| header.threshold as usize);
+    let (deps, password) = unlock(profile);
+    // recipients of the document have the document key wrapped for them, the cluster is not asked
+    let wrapped_key = header.and_then(|header| {
+        let public = deps.accounts.account_public(deps.address, &password).ok()?;
+        header.wrapped_key(&public).map(<[u8]>::to_vec)
+    });
+    // document key is recovered here, envelopes and chunks are AES-256-GCM and legacy documents
+    // AES-128-CTR
+    let key = match wrapped_key {
+        Some(wrapped_key) => unwrap_document_key(&deps, &password, &wrapped_key),
+        None => retrieve_document_key(profile, &deps, &password, &dockey_id, threshold),
+    };
+    drop(password);
+    let (cipher, encrypted_document) = match document {
+        Document::Envelope(envelope) => (Cipher::Aes256Gcm, envelope.ciphertext),
+        Document::Legacy(ref encrypted_document) => (
+            Cipher::Aes128Ctr,
+            hex::decode(encrypted_document.trim_start_matches("0x")).unwrap_or_else(|e| {
+                eprintln!("Could not read encrypted document: {}", e);
+                process::exit(1);
+            }),
+        ),
+        Document::Manifest(ref manifest) => {
+            // chunks are decrypted here and written out one by one
+            let written = write_output(output.as_ref(), |writer| {
+                stream::decrypt_chunks(manifest, &key, &*storage, writer).map(|_| ())
+            });
+            if let Err(e) = written {
+                eprintln!("Could not decrypt document: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+    };
+    let decrypted_document = cipher
+        .decrypt(&key, encrypted_document)
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Could not decrypt document: {} ({})",
+                e.message,
+                details(&e)
+            );
+            process::exit(1);
+        });
+    let document = if legacy {
+        // legacy documents encrypted a base64 copy of the file, a wrong key fails the hash check
+        String::from_utf8(decrypted_document)
+            .ok()
+            .and_then(|encoded| base64::decode(&encoded).ok())
+            .unwrap_or_default()
+    } else {
+        decrypted_document
+    };
+    let actual = envelope::plaintext_hash(&document);
+    if let Err(e) = envelope::check_plaintext_hash(dockey_id, actual) {
+        eprintln!("Could not decrypt document: {}", e);
+        process::exit(1);
+    }
+    if let Err(e) = write_output(output.as_ref(), |writer| writer.write_all(&document)) {
+        eprintln!("Could not write document: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Writes raw bytes to the output file, replacing it only once `write` succeeded, or to stdout.
+fn write_output<F>(output: Option<&PathBuf>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    match output {
+        Some(path) => {
+            let mut file = AtomicFile::create(path)?;
+            write(&mut file)?;
+            file.commit()
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            write(&mut stdout)?;
+            stdout.flush()
+        }
+    }
+}
+
+/// A directory output takes the file name the document was encrypted from.
+fn output_path(profile: &Profile, dockey_id: &H256, path: PathBuf) -> PathBuf {
+    if !path.is_dir() {
+        return path;
+    }
+    let file_name = Records::open(&profile.records_path)
+        .ok()
+        .and_then(|records| records.get(dockey_id).and_then(|r| r.file_name.clone()));
+    // only the last component, the records file could have been edited
+    match file_name
+        .as_ref()
+        .and_then(|name| Path::new(name).file_name())
+    {
+        Some(file_name) => path.join(file_name),
+        None => {
+            eprintln!(
+                "Original file name of {:?} is unknown, pass a file path to --output",
+                dockey_id
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Signs the dockey id and restores the document key from a retrieval session of the cluster.
+/// `threshold` of the envelope, records are looked up for legacy documents.
+fn retrieve_document_key(
+    profile: &Profile,
+    deps: &Dependencies,
+    password: &Password,
+    dockey_id: &H256,
+    threshold: Option<usize>,
+) -> SecretBytes {
+    let cluster = super::cluster_client(profile);
+    // TODO: should be replaced with 'select account and download encrypted file
+    let signed_dockey_id = super::sign_raw_hash(deps, password, dockey_id);
+    let threshold = threshold.or_else(|| recorded_threshold(profile, dockey_id));
+    let keys = cluster
+        .retrieve_document_key_shadow(dockey_id, &signed_dockey_id)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not retrieve document key: {}", e);
+            report_threshold(profile, threshold);
+            process::exit(1);
+        });
+    keys.validate(threshold)
+        .and_then(|_| document_key(deps, password, &keys))
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Could not restore document key: {} ({})",
+                e.message,
+                details(&e)
+            );
+            process::exit(1);
+        })
+}
+
+/// Document key wrapped for the account at encryption.
+fn unwrap_document_key(
+    deps: &Dependencies,
+    password: &Password,
+    wrapped_key: &[u8],
+) -> SecretBytes {
+    deps.decrypt(password, wrapped_key)
+        .ok()
+        .filter(|key| key.len() == 64)
+        .unwrap_or_else(|| {
+            eprintln!("Could not decrypt the document key wrapped for this account");
+            process::exit(1);
+        })
+}
+
+/// Document key restored from the shadows, which are encrypted with the account public.
+fn document_key(
+    deps: &Dependencies,
+    password: &Password,
+    keys: &DecryptedDocumentKey,
+) -> Result<SecretBytes, Error> {
+    let mut shadows = Vec::with_capacity(keys.decrypt_shadows.len());
+    for (index, shadow) in keys.decrypt_shadows.iter().enumerate() {
+        let shadow = deps.decrypt(password, &shadow.0)?;
+        let shadow = Secret::from_unsafe_slice(&shadow)
+            .map_err(|e| errors::invalid_document_key("decrypt_shadows", (index, e)))?;
+        shadows.push(shadow);
+    }
+    decrypt_with_shadow_coefficients(
+        keys.decrypted_secret.into(),
+        keys.common_point.into(),
+        shadows,
+    )
+}
+
+fn recorded_threshold(profile: &Profile, dockey_id: &H256) -> Option<usize> {
+    Records::open(&profile.records_path)
+        .ok()
+        .and_then(|records| records.get(dockey_id).map(|record| record.threshold))
+}
+
+fn details(error: &Error) -> &str {
+    error
+        .data
+        .as_ref()
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+fn report_threshold(profile: &Profile, threshold: Option<usize>) {
+    match threshold {
+        Some(threshold) => eprintln!(
+            "Document expects threshold {}: {} nodes must take part in the retrieval{}",
+            threshold,
+            threshold + 1,
+            match profile.servers.len() {
+                0 => String::new(),
+                n => format!(" (cluster has {})", n),
+            }
+        ),
+        None => eprintln!(
+            "Threshold of this document is unknown, it was not encrypted with this profile"
+        ),
+    }
 }
@@ -1,106 +1,297 @@
-use std::{io::Result, path::PathBuf};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
-use ethereum_types::{H256, H512};
-use serde_json::json;
-use url::Url;
+use ethereum_types::H256;
+use ethkey::{Password, Public};
 
-use crate::{dependency::Dependencies, document_key::EncryptedDocumentKey, util::create_dockey_id};
+use crate::{
+    args::Recipient,
+    config::Profile,
+    dependency::Dependencies,
+    envelope::{Envelope, Header, Manifest, WrappedKey},
+    helpers::{encrypt_document, ordered_servers_keccak, wrap_document_key},
+    records::{DocumentRecord, Records},
+    secretstore::SecretStore,
+    storage::{self, StorageConfig},
+    stream,
+    util::{create_dockey_id, unlock_prompt},
+};
 
-fn generate_server_key(dockey_id: H256, signed_dockey_id: &str) -> Result<String> {
-    let base = Url::parse("http://localhost:8000/shadow/").unwrap();
-    let path = format!(
-        "{}{}{}{}",
-        &hex::encode(H256::as_bytes(&dockey_id)),
-        "/",
-        signed_dockey_id,
-        "/1?apikey=fa05a2e1-d323-4723-96b7-4d2695a61d3f"
-    ); // TODO: work with threshhold
-    let url = base.join(&path).unwrap();
-    let client = reqwest::Client::new();
-    let mut buf: Vec<u8> = vec![];
-    let mut res = client.post(url.as_str()).send().unwrap();
-    res.copy_to(&mut buf).unwrap();
-    Ok(String::from_utf8(buf.to_vec()).unwrap())
+/// Command line options of `encrypt`.
+pub struct EncryptOptions {
+    pub threshold: usize,
+    pub server_generated: bool,
+    /// New file the ciphertext is also written to.
+    pub keep_ciphertext: Option<PathBuf>,
+    /// `stream::DEFAULT_CHUNK_SIZE` unless given.
+    pub chunk_size: Option<usize>,
+    pub recipients: Vec<Recipient>,
 }
 
-fn store_document_key(
-    dockey_id: H256,
-    signed_dockey_id: &str,
-    generation_response: EncryptedDocumentKey,
-) -> Result<String> {
-    let base = Url::parse("http://localhost:8000/shadow/").unwrap();
-    let path = format!(
-        "{}{}{}{}{}{}{}{}",
-        &hex::encode(H256::as_bytes(&dockey_id)),
-        "/",
-        signed_dockey_id,
-        "/",
-        &hex::encode(H512::as_bytes(&generation_response.common_point)),
-        "/",
-        &hex::encode(H512::as_bytes(&generation_response.encrypted_point)),
-        "?apikey=fa05a2e1-d323-4723-96b7-4d2695a61d3f"
-    );
-    let url = base.join(&path).unwrap();
-    let client = reqwest::Client::new();
-    let mut buf: Vec<u8> = vec![];
-    let mut res = client.post(url.as_str()).send().unwrap();
-    res.copy_to(&mut buf).unwrap();
-    Ok(String::from_utf8(buf.to_vec()).unwrap())
+pub fn encrypt(profile: &Profile, file: PathBuf, options: EncryptOptions) {
+    encrypt_as(profile, file, options, super::dependencies);
 }
 
-pub fn encrypt(file: PathBuf) {
-    let dockey_id = create_dockey_id(file.clone()).unwrap();
-    let deps = Dependencies::transient();
-    let io = deps.default_client();
-
-    let params = json!({
-        "jsonrpc": "2.0",
-        "method": "secretstore_signRawHash",
-        "params": [
-            &deps.address,
-            &deps.password,
-            &dockey_id,
-        ],
-        "id": 1
+/// Encrypts with the account `unlock` returns, asked for once the options were checked.
+/// Returns the dockey id and the location of the stored document.
+pub(super) fn encrypt_as<F>(
+    profile: &Profile,
+    file: PathBuf,
+    options: EncryptOptions,
+    unlock: F,
+) -> (H256, String)
+where
+    F: FnOnce(&Profile) -> (Dependencies, Password),
+{
+    let EncryptOptions {
+        threshold,
+        server_generated,
+        keep_ciphertext,
+        chunk_size,
+        recipients,
+    } = options;
+    let chunk_size = chunk_size.unwrap_or(stream::DEFAULT_CHUNK_SIZE);
+    if let Err(e) = check_threshold(threshold, profile.servers.len()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    if chunk_size == 0 || chunk_size > u32::max_value() as usize {
+        eprintln!("Chunk size must be between 1 and {}", u32::max_value());
+        process::exit(1);
+    }
+    let storage = storage::open(profile).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
     });
-    let signed_dockey_id = io.handle_request_sync(&params.to_string()).unwrap();
-    let signed_dockey_id = signed_dockey_id.replace(r#"{"jsonrpc":"2.0","result":"0x"#, "");
-    let signed_dockey_id = signed_dockey_id.replace(r#"","id":1}"#, "");
-    let server_key = generate_server_key(dockey_id, &signed_dockey_id).unwrap();
-    let server_key = server_key.replace("\"", "");
-    let params = json!({
-        "jsonrpc": "2.0",
-        "method": "secretstore_generateDocumentKey",
-        "params": [
-            &deps.address,
-            &deps.password,
-            server_key,
-        ],
-        "id": 1
+    let recipients = recipients
+        .iter()
+        .map(|recipient| recipient_public(profile, recipient))
+        .collect::<Vec<_>>();
+    let dockey_id = create_dockey_id(file.clone()).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", file.display(), e);
+        process::exit(1);
     });
-    let generation_response = io.handle_request_sync(&params.to_string()).unwrap();
-    let generation_response = generation_response.replace(r#"{"jsonrpc":"2.0","result":"#, "");
-    let generation_response = generation_response.replace(r#","id":1}"#, "");
-    let generation_response: EncryptedDocumentKey =
-        serde_json::from_str(&generation_response).unwrap();
-    let encoded_file = deps.encode_file(file.clone());
-    let params = json!({
-        "jsonrpc": "2.0",
-        "method": "secretstore_encrypt",
-        "params": [
-            &deps.address,
-            &deps.password,
-            &generation_response.encrypted_key,
-            "0x".to_owned() + &hex::encode(encoded_file).to_string(),
-        ],
-        "id": 1
+    let (deps, password) = unlock(profile);
+    let cluster = super::cluster_client(profile);
+
+    let signed_dockey_id = super::sign_raw_hash(&deps, &password, &dockey_id);
+    let encrypted_key = if server_generated {
+        // document key never leaves the cluster in plain form, we receive it encrypted with
+        // our account public and the cluster keeps it stored
+        cluster
+            .generate_document_key(&dockey_id, &signed_dockey_id, threshold)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not generate document key: {}", e);
+                process::exit(1);
+            })
+    } else {
+        let server_key = cluster
+            .generate_server_key(&dockey_id, &signed_dockey_id, threshold)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not generate server key: {}", e);
+                process::exit(1);
+            });
+        let generation_response = deps
+            .client()
+            .generate_document_key(deps.address, password.clone(), server_key)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not generate document key: {}", e.message);
+                process::exit(1);
+            });
+        // stored before anything is uploaded, a failure leaves no ciphertext behind
+        cluster
+            .store_document_key(
+                &dockey_id,
+                &signed_dockey_id,
+                &generation_response.common_point,
+                &generation_response.encrypted_point,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("Could not store document key: {}", e);
+                process::exit(1);
+            });
+        generation_response.encrypted_key
+    };
+    // plain document key, for the chunks and the recipients
+    let key = deps
+        .decrypt(&password, &encrypted_key.0)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not decrypt document key: {}", e.message);
+            process::exit(1);
+        });
+    drop(password);
+    let recipients = recipients
+        .iter()
+        .map(|public| WrappedKey {
+            public: *public,
+            encrypted_key: wrap_document_key(&key, public).unwrap_or_else(|e| {
+                eprintln!(
+                    "Could not wrap the document key for {:?}: {}",
+                    public, e.message
+                );
+                process::exit(1);
+            }),
+        })
+        .collect();
+    let header = Header {
+        threshold: threshold as u32,
+        dockey_id,
+        servers_set_hash: servers_set_hash(profile),
+        size: fs::metadata(&file)
+            .map(|metadata| metadata.len())
+            .unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {}", file.display(), e);
+                process::exit(1);
+            }),
+        // dockey id is the sha256 of the document
+        plaintext_hash: dockey_id,
+        recipients,
+    };
+    let (encrypted_document, chunks) = if header.size > chunk_size as u64 {
+        // read and encrypted one chunk at a time, the document is never in memory as a whole
+        let reader = File::open(&file).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", file.display(), e);
+            process::exit(1);
+        });
+        let (chunks, size) = stream::encrypt_chunks(reader, &key, chunk_size, &*storage)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not store encrypted chunks: {}", e);
+                process::exit(1);
+            });
+        let manifest = Manifest {
+            header: Header { size, ..header },
+            chunk_size: chunk_size as u32,
+            chunks,
+        };
+        (manifest.encode(), manifest.chunks)
+    } else {
+        // what secretstore_encrypt does, with the key we already hold
+        let document = fs::read(&file).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", file.display(), e);
+            process::exit(1);
+        });
+        let envelope = Envelope {
+            header,
+            ciphertext: encrypt_document(&key, document).unwrap_or_else(|e| {
+                eprintln!("Could not encrypt document: {}", e.message);
+                process::exit(1);
+            }),
+        };
+        (envelope.encode(), Vec::new())
+    };
+    // ciphertext is uploaded from memory, a local copy is only written on request
+    if let Some(path) = keep_ciphertext {
+        if let Err(e) = write_new_file(&path, &encrypted_document) {
+            eprintln!("Could not keep ciphertext in {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+    let location = storage.put(&encrypted_document).unwrap_or_else(|e| {
+        eprintln!("Could not store encrypted document: {}", e);
+        process::exit(1);
     });
-    let encryption_response = io.handle_request_sync(&params.to_string()).unwrap();
-    let encryption_response = encryption_response.replace(r#"{"jsonrpc":"2.0","result":"#, "");
-    let encryption_response = encryption_response.replace(r#","id":1}"#, "");
-    let result = deps.post_to_ipfs(dockey_id, &encryption_response).unwrap();
     println!("dockey_id: {:?}", dockey_id);
-    println!("ipfsHash: {}", result["Hash"]);
-    // done
-    store_document_key(dockey_id, &signed_dockey_id, generation_response).unwrap();
+    if let StorageConfig::Ipfs { .. } = profile.storage {
+        println!("ipfsHash: {:?}", location);
+    }
+    println!("location: {:?}", location);
+    println!("threshold: {}", threshold);
+    // done, the document is only missing from the local list if this fails
+    let mut records = Records::open(&profile.records_path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", profile.records_path.display(), e);
+        process::exit(1);
+    });
+    records.insert(DocumentRecord {
+        dockey_id,
+        location: location.clone(),
+        chunks,
+        threshold,
+        file_name: file
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    });
+    if let Err(e) = records.save() {
+        eprintln!(
+            "Could not record {} in {}: {}",
+            location,
+            profile.records_path.display(),
+            e
+        );
+        process::exit(1);
+    }
+    (dockey_id, location)
+}
+
+/// Servers set the document was encrypted for, zero when the profile does not list it.
+fn servers_set_hash(profile: &Profile) -> H256 {
+    match profile.servers.len() {
+        0 => H256::zero(),
+        _ => ordered_servers_keccak(profile.servers.clone()),
+    }
+}
+
+/// Public key of a recipient, keystore accounts are unlocked to read it.
+fn recipient_public(profile: &Profile, recipient: &Recipient) -> Public {
+    match *recipient {
+        Recipient::Public(public) => public,
+        Recipient::Address(address) => {
+            let deps = Dependencies::default(profile);
+            let password = unlock_prompt(&address).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+            deps.accounts
+                .account_public(address, &password)
+                .unwrap_or_else(|e| {
+                    eprintln!("Could not read public key of {:?}: {:?}", address, e);
+                    process::exit(1);
+                })
+        }
+    }
+}
+
+/// Writes `data` to `path`, never replacing an existing file.
+fn write_new_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(data)
+}
+
+/// Any `threshold + 1` nodes can recover the key, so the cluster needs more than `threshold` nodes.
+fn check_threshold(threshold: usize, cluster_size: usize) -> Result<(), String> {
+    if cluster_size != 0 && threshold >= cluster_size {
+        return Err(format!(
+            "Threshold {} needs at least {} nodes, cluster has {}",
+            threshold,
+            threshold + 1,
+            cluster_size
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_threshold, write_new_file};
+
+    #[test]
+    fn threshold_must_fit_cluster() {
+        assert!(check_threshold(0, 1).is_ok());
+        assert!(check_threshold(2, 3).is_ok());
+        assert!(check_threshold(3, 3).is_err());
+        // unknown cluster size is left to the nodes
+        assert!(check_threshold(5, 0).is_ok());
+    }
+
+    #[test]
+    fn kept_ciphertext_never_overwrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ciphertext");
+        write_new_file(&path, b"ciphertext").unwrap();
+        assert!(write_new_file(&path, b"other").is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"ciphertext");
+    }
 }
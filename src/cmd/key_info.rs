@@ -0,0 +1,146 @@
+use std::process;
+
+use ethereum_types::{H256, H512};
+use ethkey::Signature;
+
+use crate::{
+    cluster::{Error, SecretStoreHttpClient},
+    config::Profile,
+    records::Records,
+};
+
+/// What the cluster tells a requester about a key id.
+#[derive(Debug, PartialEq)]
+pub struct KeyInfo {
+    pub server_key: ServerKey,
+    /// `None` when the requester is not allowed to find out.
+    pub document_key_stored: Option<bool>,
+    pub threshold: Option<usize>,
+    pub retrievable: bool,
+}
+
+/// Server key as seen by the requester.
+#[derive(Debug, PartialEq)]
+pub enum ServerKey {
+    NotGenerated,
+    /// Key exists, but the requester may not read it.
+    AccessDenied,
+    Public(H512),
+}
+
+pub fn key_info(profile: &Profile, dockey_id: H256) {
+    let (deps, password) = super::dependencies(profile);
+    let cluster = super::cluster_client(profile);
+
+    let signed_dockey_id = super::sign_raw_hash(&deps, &password, &dockey_id);
+
+    let mut info = query(&cluster, &dockey_id, &signed_dockey_id).unwrap_or_else(|e| {
+        eprintln!("Could not query key: {}", e);
+        process::exit(1);
+    });
+    if info.threshold.is_none() {
+        info.threshold = Records::open(&profile.records_path)
+            .ok()
+            .and_then(|records| records.get(&dockey_id).map(|record| record.threshold));
+    }
+
+    println!("dockey_id: {:?}", dockey_id);
+    println!("account: {:?}", deps.address);
+    match info.server_key {
+        ServerKey::Public(public) => println!("server_key_public: {:?}", public),
+        ServerKey::NotGenerated => println!("server_key_public: not generated"),
+        ServerKey::AccessDenied => println!("server_key_public: access denied"),
+    }
+    match info.document_key_stored {
+        Some(stored) => println!("document_key_stored: {}", stored),
+        None => println!("document_key_stored: unknown"),
+    }
+    match info.threshold {
+        Some(threshold) => println!("threshold: {}", threshold),
+        None => println!("threshold: unknown"),
+    }
+    println!("retrievable: {}", info.retrievable);
+}
+
+/// Looks the key up with the server key and document key shadow retrieval sessions.
+///
+/// Missing keys and denied access are part of the answer, every other error is returned.
+pub fn query(
+    cluster: &SecretStoreHttpClient,
+    dockey_id: &H256,
+    signature: &Signature,
+) -> Result<KeyInfo, Error> {
+    let mut info = KeyInfo {
+        server_key: ServerKey::NotGenerated,
+        document_key_stored: None,
+        threshold: None,
+        retrievable: false,
+    };
+    info.server_key = match cluster.retrieve_server_key(dockey_id, signature) {
+        Ok(public) => ServerKey::Public(public),
+        Err(Error::ServerKeyNotFound(_)) => {
+            info.document_key_stored = Some(false);
+            return Ok(info);
+        }
+        Err(Error::AccessDenied(_)) => ServerKey::AccessDenied,
+        Err(e) => return Err(e),
+    };
+    match cluster.retrieve_document_key_shadow(dockey_id, signature) {
+        Ok(keys) => {
+            // every shadow comes from one of the threshold + 1 nodes of the session
+            info.document_key_stored = Some(true);
+            info.threshold = keys.decrypt_shadows.len().checked_sub(1);
+            info.retrievable = true;
+        }
+        Err(Error::DocumentKeyNotFound(_)) => info.document_key_stored = Some(false),
+        Err(Error::ServerKeyNotFound(_)) => {
+            info.server_key = ServerKey::NotGenerated;
+            info.document_key_stored = Some(false);
+        }
+        Err(Error::AccessDenied(_)) => (),
+        Err(e) => return Err(e),
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{helpers::generate_document_key, mock};
+    use ethkey::{Generator, Random};
+
+    #[test]
+    fn reports_registration_progress() {
+        let cluster = SecretStoreHttpClient::new(&mock::spawn(3), None).unwrap();
+        let requester = Random.generate().unwrap();
+        let dockey_id = H256::random();
+        let signature = ethkey::sign(requester.secret(), &dockey_id).unwrap();
+
+        let info = query(&cluster, &dockey_id, &signature).unwrap();
+        assert_eq!(info.server_key, ServerKey::NotGenerated);
+        assert_eq!(info.document_key_stored, Some(false));
+        assert!(!info.retrievable);
+
+        let server_key = cluster
+            .generate_server_key(&dockey_id, &signature, 1)
+            .unwrap();
+        let info = query(&cluster, &dockey_id, &signature).unwrap();
+        assert_eq!(info.server_key, ServerKey::Public(server_key));
+        assert_eq!(info.document_key_stored, Some(false));
+        assert!(!info.retrievable);
+
+        let document_key = generate_document_key(*requester.public(), server_key.into()).unwrap();
+        cluster
+            .store_document_key(
+                &dockey_id,
+                &signature,
+                &document_key.common_point,
+                &document_key.encrypted_point,
+            )
+            .unwrap();
+        let info = query(&cluster, &dockey_id, &signature).unwrap();
+        assert_eq!(info.document_key_stored, Some(true));
+        assert_eq!(info.threshold, Some(1));
+        assert!(info.retrievable);
+    }
+}
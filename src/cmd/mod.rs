@@ -1,7 +1,134 @@
 mod address;
+mod admin;
 mod decrypt;
 mod encrypt;
+mod key_info;
+mod pin;
+mod sign;
+mod sign_message;
+mod verify;
 
 pub use address::address;
+pub use admin::servers_set_change;
 pub use decrypt::decrypt;
-pub use encrypt::encrypt;
+pub use encrypt::{encrypt, EncryptOptions};
+pub use key_info::key_info;
+pub use pin::{pin_add, pin_ls, pin_rm};
+pub use sign::sign;
+pub use sign_message::{sign_message, sign_typed_data};
+pub use verify::{recover, verify};
+
+use ethkey::Signature;
+
+use std::process;
+
+use ethereum_types::H256;
+use ethkey::Password;
+
+use crate::{cluster::SecretStoreHttpClient, config::Profile, dependency::Dependencies};
+
+fn cluster_client(profile: &Profile) -> SecretStoreHttpClient {
+    SecretStoreHttpClient::with_nodes(
+        &profile.secret_store_urls,
+        profile.api_key.clone(),
+        profile.request_policy.clone(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("profile '{}': {}", profile.name, e);
+        process::exit(1);
+    })
+}
+
+/// Account of the profile and its password, which the command drops once it is done.
+fn dependencies(profile: &Profile) -> (Dependencies, Password) {
+    Dependencies::new(profile).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+/// `hash` signed with the account, as the cluster expects requester signatures.
+fn sign_raw_hash(deps: &Dependencies, password: &Password, hash: &H256) -> Signature {
+    deps.sign_raw_hash(password, hash).unwrap_or_else(|e| {
+        eprintln!("Could not sign {:?} with the account: {}", hash, e.message);
+        process::exit(1);
+    })
+}
+
+/// Cluster may return `v` in electrum form (27/28).
+fn ecdsa_signature(signature: &[u8]) -> Option<Signature> {
+    if signature.len() != 65 {
+        return None;
+    }
+    let mut raw = [0u8; 65];
+    raw.copy_from_slice(signature);
+    if raw[64] >= 27 {
+        raw[64] -= 27;
+    }
+    Some(raw.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt::decrypt_as, encrypt::encrypt_as, EncryptOptions};
+    use crate::{
+        cluster::RequestPolicy, config::Profile, dependency::Dependencies, mock,
+        storage::StorageConfig,
+    };
+    use ethereum_types::H256;
+    use ethkey::Password;
+    use std::{collections::BTreeSet, fs};
+
+    #[test]
+    fn encrypt_and_decrypt_through_mock_cluster() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let profile = Profile {
+            name: "test".into(),
+            secret_store_urls: vec![mock::spawn(3)],
+            api_key: None,
+            ipfs_url: String::new(),
+            keystore_dir: root.join("keys"),
+            account: None,
+            records_path: root.join("records.json"),
+            servers: BTreeSet::new(),
+            request_policy: RequestPolicy::default(),
+            storage: StorageConfig::Fs {
+                path: root.join("documents"),
+            },
+        };
+        let account = Dependencies::transient();
+        let unlock = |_: &Profile| {
+            let deps = Dependencies {
+                address: account.address,
+                accounts: account.accounts.clone(),
+            };
+            (deps, Password::from(""))
+        };
+
+        // the larger document is stored in chunks under a manifest
+        for &(size, server_generated) in &[(100, false), (3000, true)] {
+            let document: Vec<u8> = H256::random()
+                .as_bytes()
+                .iter()
+                .cycle()
+                .take(size)
+                .cloned()
+                .collect();
+            let file = root.join(format!("document-{}", size));
+            fs::write(&file, &document).unwrap();
+            let options = EncryptOptions {
+                threshold: 1,
+                server_generated,
+                keep_ciphertext: None,
+                chunk_size: Some(1024),
+                recipients: Vec::new(),
+            };
+            let (dockey_id, location) = encrypt_as(&profile, file, options, unlock);
+
+            let output = root.join(format!("decrypted-{}", size));
+            decrypt_as(&profile, dockey_id, location, Some(output.clone()), unlock);
+            assert_eq!(fs::read(&output).unwrap(), document);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::{iter, process};
+
+use ethereum_types::H256;
+
+use crate::{
+    config::Profile,
+    records::{DocumentRecord, Records},
+    storage::{self, Cid, IpfsStorage},
+};
+
+pub fn pin_add(profile: &Profile, cid: Cid) {
+    let ipfs = ipfs(profile);
+    ipfs.pin_add(&cid).unwrap_or_else(|e| exit(e));
+    println!("pinned {}", cid);
+}
+
+/// Unpins `cid`, refusing documents listed in the records unless `force` is set.
+pub fn pin_rm(profile: &Profile, cid: Cid, force: bool) {
+    let ipfs = ipfs(profile);
+    let records = records(profile);
+    let in_use: Vec<_> = documents_of(&cid, records.documents()).collect();
+    if !in_use.is_empty() && !force {
+        for dockey_id in in_use {
+            eprintln!("{} holds the document of dockey_id {:?}", cid, dockey_id);
+        }
+        eprintln!("Pass --force to unpin it anyway");
+        process::exit(1);
+    }
+    ipfs.pin_rm(&cid).unwrap_or_else(|e| exit(e));
+    println!("unpinned {}", cid);
+}
+
+/// Lists pins with the dockey ids they hold, then recorded documents the node does not pin.
+pub fn pin_ls(profile: &Profile) {
+    let ipfs = ipfs(profile);
+    let records = records(profile);
+    let mut pins = Vec::new();
+    for key in ipfs.pins().unwrap_or_else(|e| exit(e)) {
+        // other kinds of CIDs cannot hold a recorded document, they are listed as they are
+        match key.parse::<Cid>() {
+            Ok(cid) => pins.push(cid),
+            Err(e) => {
+                eprintln!("{}", e);
+                println!("{} -", key);
+            }
+        }
+    }
+    pins.sort();
+
+    for cid in &pins {
+        let dockey_ids: Vec<_> = documents_of(cid, records.documents())
+            .map(|dockey_id| format!("{:?}", dockey_id))
+            .collect();
+        match dockey_ids.len() {
+            0 => println!("{} -", cid),
+            _ => println!("{} {}", cid, dockey_ids.join(",")),
+        }
+    }
+    for (location, record) in unpinned(&pins, records.documents()) {
+        eprintln!(
+            "not pinned: {} (dockey_id {:?})",
+            location, record.dockey_id
+        );
+    }
+}
+
+/// Dockey ids of the recorded documents stored under `cid`, as a whole or as a chunk, whatever
+/// its version.
+fn documents_of<'a>(
+    cid: &'a Cid,
+    documents: &'a [DocumentRecord],
+) -> impl Iterator<Item = H256> + 'a {
+    let cid = cid.to_v1();
+    documents
+        .iter()
+        .filter(move |record| {
+            locations(record).any(|location| location_cid(location).as_ref() == Some(&cid))
+        })
+        .map(|record| record.dockey_id)
+}
+
+/// Recorded IPFS locations missing from `pins`, with their document.
+fn unpinned<'a>(
+    pins: &[Cid],
+    documents: &'a [DocumentRecord],
+) -> Vec<(&'a str, &'a DocumentRecord)> {
+    let pins: Vec<_> = pins.iter().map(Cid::to_v1).collect();
+    documents
+        .iter()
+        .flat_map(|record| locations(record).map(move |location| (location, record)))
+        .filter(|(location, _)| location_cid(location).map_or(false, |cid| !pins.contains(&cid)))
+        .collect()
+}
+
+/// Location of the document, then of its chunks when it is chunked.
+fn locations(record: &DocumentRecord) -> impl Iterator<Item = &str> {
+    iter::once(&record.location)
+        .chain(&record.chunks)
+        .map(String::as_str)
+}
+
+/// Documents kept by another storage backend have no CID.
+fn location_cid(location: &str) -> Option<Cid> {
+    location.parse::<Cid>().ok().map(|cid| cid.to_v1())
+}
+
+fn ipfs(profile: &Profile) -> IpfsStorage {
+    storage::ipfs(profile).unwrap_or_else(|e| exit(e))
+}
+
+fn records(profile: &Profile) -> Records {
+    Records::open(&profile.records_path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", profile.records_path.display(), e);
+        process::exit(1);
+    })
+}
+
+fn exit<E: std::fmt::Display, T>(error: E) -> T {
+    eprintln!("{}", error);
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(dockey_id: u64, location: &str) -> DocumentRecord {
+        DocumentRecord {
+            dockey_id: H256::from_low_u64_be(dockey_id),
+            location: location.into(),
+            chunks: Vec::new(),
+            threshold: 1,
+            file_name: None,
+        }
+    }
+
+    #[test]
+    fn cross_references_pins_with_records() {
+        let v0 = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR";
+        let v1 = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+        let other = "QmNfnryjxcGQJQfJRoaFv7D1kCRWirgUFMW7YCXjTZkXi4";
+        let documents = vec![
+            record(1, v0),
+            record(2, v1),
+            record(3, other),
+            record(
+                4,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+        ];
+        let pins: Vec<Cid> = vec![v0.parse().unwrap()];
+
+        let held: Vec<_> = documents_of(&pins[0], &documents).collect();
+        assert_eq!(
+            held,
+            vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]
+        );
+        let missing = unpinned(&pins, &documents);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, other);
+    }
+
+    #[test]
+    fn cross_references_chunks() {
+        let manifest = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR";
+        let chunk = "QmNfnryjxcGQJQfJRoaFv7D1kCRWirgUFMW7YCXjTZkXi4";
+        let mut chunked = record(1, manifest);
+        chunked.chunks = vec![chunk.into()];
+        let documents = vec![chunked];
+        let pins: Vec<Cid> = vec![manifest.parse().unwrap()];
+
+        let held: Vec<_> = documents_of(&chunk.parse().unwrap(), &documents).collect();
+        assert_eq!(held, vec![H256::from_low_u64_be(1)]);
+        let missing = unpinned(&pins, &documents);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, chunk);
+        assert_eq!(missing[0].1.dockey_id, H256::from_low_u64_be(1));
+    }
+}
@@ -0,0 +1,45 @@
+use std::process;
+
+use ethereum_types::H256;
+use ethkey::verify_public;
+
+use crate::{args::SigningScheme, config::Profile};
+
+pub fn sign(profile: &Profile, key_id: H256, message_hash: H256, scheme: SigningScheme) {
+    let (deps, password) = super::dependencies(profile);
+    let cluster = super::cluster_client(profile);
+
+    let signed_key_id = super::sign_raw_hash(&deps, &password, &key_id);
+
+    let server_key_public = cluster
+        .retrieve_server_key(&key_id, &signed_key_id)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not retrieve server key: {}", e);
+            process::exit(1);
+        });
+    let encrypted_signature = match scheme {
+        SigningScheme::Schnorr => cluster.schnorr_sign(&key_id, &signed_key_id, &message_hash),
+        SigningScheme::Ecdsa => cluster.ecdsa_sign(&key_id, &signed_key_id, &message_hash),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("Signing session failed: {}", e);
+        process::exit(1);
+    });
+    // combined signature is encrypted with the requester public
+    let signature = deps
+        .decrypt(&password, &encrypted_signature.0)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not decrypt signature: {}", e.message);
+            process::exit(1);
+        });
+
+    println!("scheme: {}", scheme);
+    println!("signature: 0x{}", hex::encode(&signature));
+    println!("server_key_public: {:?}", server_key_public);
+    if let SigningScheme::Ecdsa = scheme {
+        let verified = super::ecdsa_signature(&signature)
+            .map(|s| verify_public(&server_key_public, &s, &message_hash).unwrap_or(false))
+            .unwrap_or(false);
+        println!("verified: {}", verified);
+    }
+}
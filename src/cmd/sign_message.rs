@@ -0,0 +1,57 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    bytes::Bytes, config::Profile, eip712::TypedData, helpers::personal_message_hash,
+    secretstore::SecretStore,
+};
+
+/// EIP-191 `personal_sign` of the message with the profile account.
+pub fn sign_message(profile: &Profile, message: String) {
+    let (deps, password) = super::dependencies(profile);
+    println!("hash: {:?}", personal_message_hash(message.as_bytes()));
+
+    let signature = deps
+        .client()
+        .sign_personal_message(deps.address, password, Bytes::new(message.into_bytes()))
+        .unwrap_or_else(|e| {
+            eprintln!("Could not sign message: {}", e.message);
+            process::exit(1);
+        });
+    println!("signature: 0x{}", hex::encode(&signature.0));
+    println!("address: {:?}", deps.address);
+}
+
+/// EIP-712 signature of the typed data in `file` with the profile account.
+pub fn sign_typed_data(profile: &Profile, file: PathBuf) {
+    let typed_data = read_typed_data(&file);
+    let hash = typed_data.hash().unwrap_or_else(|e| {
+        eprintln!("Invalid typed data: {}", e);
+        process::exit(1);
+    });
+    let (deps, password) = super::dependencies(profile);
+    println!("hash: {:?}", hash);
+
+    let signature = deps
+        .client()
+        .sign_typed_data(deps.address, password, typed_data)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not sign typed data: {}", e.message);
+            process::exit(1);
+        });
+    println!("signature: 0x{}", hex::encode(&signature.0));
+    println!("address: {:?}", deps.address);
+}
+
+pub(super) fn read_typed_data(file: &Path) -> TypedData {
+    fs::read(file)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("Could not read typed data from {}: {}", file.display(), e);
+            process::exit(1);
+        })
+}
@@ -0,0 +1,88 @@
+use std::process;
+
+use ethereum_types::{H160, H256};
+use ethkey::{public_to_address, Public, Signature};
+
+use crate::{args::SignedHash, helpers::personal_message_hash, util::create_dockey_id};
+
+/// Prints the address and public key that signed the hash.
+pub fn recover(signature: String, signed: SignedHash) {
+    let (address, public) = signer(&signature, &signed);
+    println!("address: {:?}", address);
+    println!("public: {:?}", public);
+}
+
+/// Like `recover`, exiting with an error unless `expected` signed the hash.
+pub fn verify(signature: String, signed: SignedHash, expected: H160) {
+    let (address, public) = signer(&signature, &signed);
+    println!("address: {:?}", address);
+    println!("public: {:?}", public);
+    println!("verified: {}", address == expected);
+    if address != expected {
+        eprintln!("Signature was made by {:?}, not {:?}", address, expected);
+        process::exit(1);
+    }
+}
+
+/// Signer of a recoverable signature, as made by `secretstore_signRawHash` or the message
+/// signing methods.
+pub fn recover_signer(signature: &Signature, hash: &H256) -> Result<(H160, Public), ethkey::Error> {
+    let public = ethkey::recover(signature, hash)?;
+    Ok((public_to_address(&public), public))
+}
+
+fn signer(signature: &str, signed: &SignedHash) -> (H160, Public) {
+    let signature = hex::decode(signature.trim_start_matches("0x"))
+        .ok()
+        .and_then(|signature| super::ecdsa_signature(&signature))
+        .unwrap_or_else(|| {
+            eprintln!("Signature must be 65 bytes of hex");
+            process::exit(1);
+        });
+    let hash = match (
+        &signed.hash,
+        &signed.file,
+        &signed.message,
+        &signed.typed_data,
+    ) {
+        (Some(hash), ..) => *hash,
+        // dockey ids are the sha256 of the encrypted file
+        (_, Some(file), ..) => create_dockey_id(file.clone()).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", file.display(), e);
+            process::exit(1);
+        }),
+        (_, _, Some(message), _) => personal_message_hash(message.as_bytes()),
+        (_, _, _, Some(file)) => super::sign_message::read_typed_data(file)
+            .hash()
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid typed data: {}", e);
+                process::exit(1);
+            }),
+        _ => unreachable!("--hash, --file, --message or --typed-data is required"),
+    };
+    println!("hash: {:?}", hash);
+    recover_signer(&signature, &hash).unwrap_or_else(|e| {
+        eprintln!("Could not recover signer: {}", e);
+        process::exit(1);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethkey::{sign, Generator, Random};
+
+    #[test]
+    fn recovers_signer_of_hash() {
+        let key_pair = Random.generate().unwrap();
+        let hash = H256::random();
+        let signature = sign(key_pair.secret(), &hash).unwrap();
+
+        let (address, public) = recover_signer(&signature, &hash).unwrap();
+        assert_eq!(address, key_pair.address());
+        assert_eq!(public, *key_pair.public());
+
+        let other = recover_signer(&signature, &H256::random());
+        assert!(other.map_or(true, |(address, _)| address != key_pair.address()));
+    }
+}
@@ -0,0 +1,316 @@
+//! Configuration profiles.
+//!
+//! Profiles are read from `$CONFIG_DIR/secret-store-cli/config.toml` (or the file named by
+//! `SECRET_STORE_CONFIG`), e.g.
+//!
+//! ```toml
+//! default_profile = "dev"
+//!
+//! [profiles.dev]
+//! secret_store_url = "http://localhost:8000"
+//! ipfs_url = "http://127.0.0.1:5001"
+//! keystore_dir = "data"
+//!
+//! [profiles.prod]
+//! secret_store_urls = ["https://ss1.example.com", "https://ss2.example.com"]
+//! read_timeout = 60
+//! api_key = "fa05a2e1-d323-4723-96b7-4d2695a61d3f"
+//! account = "0x00dfe63b22312ab4329ad0d28cad8af987a01932"
+//! servers = [
+//!     "0x843645726384530ffb0c52f175278143b5a93959af7864460f5a4fec9afd1450cfb8aef63dec90657f43f55b13e0a73c7524d4e9a13c051b4e5f1e53f39ecd91",
+//! ]
+//! ```
+//!
+//! Every profile value can be overridden with a `SECRET_STORE_*` environment variable.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ethereum_types::{H160, H512};
+
+use crate::{cluster::RequestPolicy, storage::StorageConfig};
+
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DEFAULT_SECRET_STORE_URL: &str = "http://localhost:8000";
+pub const DEFAULT_IPFS_URL: &str = "http://127.0.0.1:5001";
+pub const DEFAULT_KEYSTORE_DIR: &str = "data";
+pub const DEFAULT_RECORDS_PATH: &str = "documents.json";
+
+const ENV_CONFIG: &str = "SECRET_STORE_CONFIG";
+const ENV_PROFILE: &str = "SECRET_STORE_PROFILE";
+const ENV_URL: &str = "SECRET_STORE_URL";
+const ENV_API_KEY: &str = "SECRET_STORE_API_KEY";
+const ENV_IPFS_URL: &str = "SECRET_STORE_IPFS_URL";
+const ENV_KEYSTORE_DIR: &str = "SECRET_STORE_KEYSTORE_DIR";
+const ENV_ACCOUNT: &str = "SECRET_STORE_ACCOUNT";
+const ENV_RECORDS_PATH: &str = "SECRET_STORE_RECORDS_PATH";
+
+/// Contents of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// Profile as written in the configuration file, every value is optional.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub secret_store_url: Option<String>,
+    /// Nodes tried in order, takes precedence over `secret_store_url`.
+    pub secret_store_urls: Vec<String>,
+    pub api_key: Option<String>,
+    pub ipfs_url: Option<String>,
+    pub keystore_dir: Option<PathBuf>,
+    pub account: Option<H160>,
+    pub records_path: Option<PathBuf>,
+    pub servers: BTreeSet<H512>,
+    /// Seconds.
+    pub connect_timeout: Option<u64>,
+    /// Seconds.
+    pub read_timeout: Option<u64>,
+    pub retries: Option<usize>,
+    /// Milliseconds.
+    pub retry_backoff: Option<u64>,
+    pub storage: Option<StorageConfig>,
+}
+
+/// Fully resolved profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub secret_store_urls: Vec<String>,
+    pub api_key: Option<String>,
+    pub ipfs_url: String,
+    pub keystore_dir: PathBuf,
+    pub account: Option<H160>,
+    /// File listing the documents encrypted through this profile.
+    pub records_path: PathBuf,
+    /// Public keys of the cluster nodes, empty when unknown.
+    pub servers: BTreeSet<H512>,
+    pub request_policy: RequestPolicy,
+    /// Where ciphertexts are kept.
+    pub storage: StorageConfig,
+}
+
+impl Config {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| format!("Invalid configuration: {}", e))
+    }
+
+    /// Reads the configuration file, a missing file yields an empty configuration.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                Config::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Resolves a profile, applying defaults and overrides from `var`.
+    ///
+    /// The profile is picked from `name`, then `SECRET_STORE_PROFILE`, then `default_profile`.
+    pub fn profile<F>(&self, name: Option<&str>, var: F) -> Result<Profile, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let requested = name.map(Into::into).or_else(|| var(ENV_PROFILE));
+        let name = requested
+            .clone()
+            .or_else(|| self.default_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.into());
+        let config = match self.profiles.get(&name) {
+            Some(config) => config.clone(),
+            None if requested.is_none() && name == DEFAULT_PROFILE => ProfileConfig::default(),
+            None => return Err(format!("Profile '{}' is not configured", name)),
+        };
+        let account = match var(ENV_ACCOUNT) {
+            Some(account) => Some(
+                account
+                    .trim_start_matches("0x")
+                    .parse()
+                    .map_err(|e| format!("Invalid {}: {:?}", ENV_ACCOUNT, e))?,
+            ),
+            None => config.account,
+        };
+
+        let secret_store_urls = match var(ENV_URL) {
+            Some(urls) => urls.split(',').map(|url| url.trim().to_owned()).collect(),
+            None if !config.secret_store_urls.is_empty() => config.secret_store_urls,
+            None => vec![config
+                .secret_store_url
+                .unwrap_or_else(|| DEFAULT_SECRET_STORE_URL.into())],
+        };
+        let default_policy = RequestPolicy::default();
+        let request_policy = RequestPolicy {
+            connect_timeout: config
+                .connect_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default_policy.connect_timeout),
+            read_timeout: config
+                .read_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default_policy.read_timeout),
+            retries: config.retries.unwrap_or(default_policy.retries),
+            backoff: config
+                .retry_backoff
+                .map(Duration::from_millis)
+                .unwrap_or(default_policy.backoff),
+        };
+
+        Ok(Profile {
+            secret_store_urls,
+            api_key: var(ENV_API_KEY).or(config.api_key),
+            ipfs_url: var(ENV_IPFS_URL)
+                .or(config.ipfs_url)
+                .unwrap_or_else(|| DEFAULT_IPFS_URL.into()),
+            keystore_dir: var(ENV_KEYSTORE_DIR)
+                .map(PathBuf::from)
+                .or(config.keystore_dir)
+                .unwrap_or_else(|| DEFAULT_KEYSTORE_DIR.into()),
+            records_path: var(ENV_RECORDS_PATH)
+                .map(PathBuf::from)
+                .or(config.records_path)
+                .unwrap_or_else(|| DEFAULT_RECORDS_PATH.into()),
+            servers: config.servers,
+            request_policy,
+            storage: config.storage.unwrap_or_default(),
+            account,
+            name,
+        })
+    }
+}
+
+/// Default location of the configuration file.
+pub fn config_path() -> PathBuf {
+    match env::var_os(ENV_CONFIG) {
+        Some(path) => path.into(),
+        None => dirs::config_dir()
+            .unwrap_or_default()
+            .join("secret-store-cli")
+            .join("config.toml"),
+    }
+}
+
+/// Loads the configuration file and resolves the requested profile against the environment.
+pub fn load(profile: Option<&str>) -> Result<Profile, String> {
+    Config::read(&config_path())?.profile(profile, |key| env::var(key).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default_profile = "dev"
+
+[profiles.dev]
+secret_store_url = "http://localhost:8000"
+
+[profiles.prod]
+secret_store_urls = ["https://ss1.example.com", "https://ss2.example.com"]
+read_timeout = 60
+retries = 0
+api_key = "secret"
+ipfs_url = "http://127.0.0.1:5001"
+keystore_dir = "/var/lib/secret-store"
+account = "0x00dfe63b22312ab4329ad0d28cad8af987a01932"
+servers = [
+    "0x843645726384530ffb0c52f175278143b5a93959af7864460f5a4fec9afd1450cfb8aef63dec90657f43f55b13e0a73c7524d4e9a13c051b4e5f1e53f39ecd91",
+    "0x07230e34ebfe41337d3ed53b186b3861751f2401ee74b988bba55694e2a6f60c757677e194be2e53c3523cc8548694e636e6acb35c4e8fdc5e29d28679b9b2f3",
+]
+
+[profiles.prod.storage]
+backend = "s3"
+endpoint = "http://127.0.0.1:9000"
+bucket = "ciphertexts"
+"#;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn resolves_default_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+        let profile = config.profile(None, no_env).unwrap();
+        assert_eq!(profile.name, "dev");
+        assert_eq!(profile.secret_store_urls, vec!["http://localhost:8000"]);
+        assert_eq!(profile.request_policy, RequestPolicy::default());
+        assert_eq!(profile.api_key, None);
+        assert_eq!(profile.ipfs_url, DEFAULT_IPFS_URL);
+        assert_eq!(profile.keystore_dir, PathBuf::from(DEFAULT_KEYSTORE_DIR));
+        assert_eq!(profile.storage, StorageConfig::default());
+    }
+
+    #[test]
+    fn resolves_named_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+        let profile = config.profile(Some("prod"), no_env).unwrap();
+        assert_eq!(
+            profile.secret_store_urls,
+            vec!["https://ss1.example.com", "https://ss2.example.com"]
+        );
+        assert_eq!(profile.request_policy.read_timeout, Duration::from_secs(60));
+        assert_eq!(profile.request_policy.retries, 0);
+        assert_eq!(profile.api_key, Some("secret".into()));
+        assert_eq!(profile.ipfs_url, "http://127.0.0.1:5001");
+        assert_eq!(profile.keystore_dir, PathBuf::from("/var/lib/secret-store"));
+        assert_eq!(
+            profile.account,
+            Some("00dfe63b22312ab4329ad0d28cad8af987a01932".parse().unwrap())
+        );
+        assert_eq!(profile.servers.len(), 2);
+        assert_eq!(
+            profile.storage,
+            StorageConfig::S3 {
+                endpoint: "http://127.0.0.1:9000".into(),
+                bucket: "ciphertexts".into(),
+                region: "us-east-1".into(),
+                access_key: None,
+                secret_key: None,
+            }
+        );
+    }
+
+    #[test]
+    fn environment_overrides_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+        let profile = config
+            .profile(None, |key| match key {
+                ENV_PROFILE => Some("prod".into()),
+                ENV_URL => Some("http://10.0.0.1:8000, http://10.0.0.2:8000".into()),
+                ENV_API_KEY => Some("other".into()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(profile.name, "prod");
+        assert_eq!(
+            profile.secret_store_urls,
+            vec!["http://10.0.0.1:8000", "http://10.0.0.2:8000"]
+        );
+        assert_eq!(profile.api_key, Some("other".into()));
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let profile = Config::default().profile(None, no_env).unwrap();
+        assert_eq!(profile.name, DEFAULT_PROFILE);
+        assert_eq!(profile.secret_store_urls, vec![DEFAULT_SECRET_STORE_URL]);
+    }
+
+    #[test]
+    fn unknown_profile_is_error() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert!(config.profile(Some("staging"), no_env).is_err());
+        assert!(Config::parse("[profiles.dev]\nurl = \"x\"").is_err());
+    }
+}
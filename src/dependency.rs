@@ -1,152 +1,153 @@
-use std::{
-    fs::File,
-    io::{BufReader, Read, Result, Write},
-    path::PathBuf,
-    sync::Arc,
-};
+use std::sync::Arc;
 
-use base64::{decode, encode};
 use ethcore_accounts::{AccountProvider, AccountProviderSettings};
 use ethereum_types::{H160, H256};
-use ethkey::Password;
+use ethkey::{Password, Signature};
 use ethstore::accounts_dir::RootDiskDirectory;
-use jsonrpc_core::IoHandler;
-use serde_json::Value;
+use jsonrpc_core::{Error, IoHandler};
+
+use parity_crypto::DEFAULT_MAC;
 
 use crate::{
+    config::Profile,
+    document_key::SecretBytes,
+    errors,
     metadata::Metadata,
     secretstore::{SecretStore, SecretStoreClient},
-    util::{accounts_prompt, password_prompt},
+    util::{password_prompt, unlock_prompt},
 };
 
+/// Account and keystore of a command. The password is not kept here, `new` hands it to the
+/// command, which passes it to the calls that unlock the account.
 pub struct Dependencies {
     pub address: H160,
     pub accounts: Arc<AccountProvider>,
-    pub password: Password,
 }
 
 impl Dependencies {
-    pub fn new() -> Self {
-        let dir = RootDiskDirectory::create("data").unwrap();
-        let store = ethstore::EthStore::open_with_iterations(Box::new(dir), 1024).unwrap();
-        let ap = AccountProvider::new(Box::new(store), AccountProviderSettings::default());
-        let accounts = ap.accounts().unwrap();
-        let mut address = H160::zero();
-        let mut password = Password::from("".to_string());
-        match accounts.len() {
-            0 => {
-                password = password_prompt().unwrap();
-                address = ap.new_account(&password).unwrap();
+    /// Account of the profile and its password, checked against the keystore.
+    pub fn new(profile: &Profile) -> Result<(Self, Password), String> {
+        let ap = disk_provider(profile);
+        let accounts = ap
+            .accounts()
+            .map_err(|e| format!("Could not list accounts: {}", e))?;
+        let account = match profile.account {
+            Some(account) if accounts.contains(&account) => Some(account),
+            Some(account) => {
+                return Err(format!(
+                    "Account {:?} not found in {}",
+                    account,
+                    profile.keystore_dir.display()
+                ));
             }
-            1 => {
-                assert_eq!(ap.default_account().unwrap(), accounts[0]);
+            None if accounts.len() > 1 => {
+                return Err(format!(
+                    "{} has multiple accounts, set `account` in the profile",
+                    profile.keystore_dir.display()
+                ));
+            }
+            None => accounts.first().cloned(),
+        };
+        let (address, password) = match account {
+            Some(account) => {
                 // password check
-                password = password_prompt().unwrap();
-                if ap.test_password(&accounts[0], &password).unwrap() {
-                    address = accounts[0];
-                } else {
-                    println!("Password does not match");
+                let password = unlock_prompt(&account)?;
+                let matches = ap
+                    .test_password(&account, &password)
+                    .map_err(|e| format!("Could not unlock {:?}: {}", account, e))?;
+                if !matches {
+                    return Err("Password does not match".into());
                 }
+                (account, password)
             }
-            _ => {
-                // TODO: account selection
-                let num = accounts_prompt(accounts);
-                println!("num is : {:?}", num);
+            None => {
+                let password = password_prompt()?;
+                let address = ap
+                    .new_account(&password)
+                    .map_err(|e| format!("Could not create account: {}", e))?;
+                (address, password)
             }
-        }
-        Dependencies {
+        };
+        let deps = Dependencies {
             address: address,
             accounts: Arc::new(ap),
-            password: password,
-        }
+        };
+        Ok((deps, password))
     }
 
+    /// In memory account with an empty password.
     pub fn transient() -> Self {
         let ap = AccountProvider::transient_provider();
-        let password = Password::from("");
-        let address = ap.new_account(&password).unwrap();
+        let address = ap.new_account(&Password::from("")).unwrap();
         Dependencies {
             address: address,
             accounts: Arc::new(ap),
-            password: password,
         }
     }
 
     // TODO: refactor
-    pub fn default() -> Self {
-        let dir = RootDiskDirectory::create("data").unwrap();
-        let store = ethstore::EthStore::open_with_iterations(Box::new(dir), 1024).unwrap();
-        let ap = AccountProvider::new(Box::new(store), AccountProviderSettings::default());
-        let address = ap.default_account().unwrap();
-        let password = Password::from("".to_string());
+    pub fn default(profile: &Profile) -> Self {
+        let ap = disk_provider(profile);
+        let address = match profile.account {
+            Some(account) => account,
+            None => ap.default_account().unwrap(),
+        };
         Dependencies {
             address: address,
             accounts: Arc::new(ap),
-            password: password,
         }
     }
 
-    fn client(&self) -> SecretStoreClient {
+    /// Calls `SecretStore` methods directly, so the password never ends up in a JSON-RPC
+    /// request string.
+    pub fn client(&self) -> SecretStoreClient {
         SecretStoreClient::new(&self.accounts)
     }
 
-    pub fn default_client(&self) -> IoHandler<Metadata> {
-        let mut io = IoHandler::default();
-        io.extend_with(self.client().to_delegate());
-        io
-    }
-
-    pub fn encode_file(&self, file: PathBuf) -> String {
-        let f = File::open(file).expect("could not open file");
-        let mut file = BufReader::new(f);
-        let mut buf = vec![];
-        file.read_to_end(&mut buf).expect("could not read file");
-        encode(&buf)
+    pub fn sign_raw_hash(&self, password: &Password, hash: &H256) -> Result<Signature, Error> {
+        let signature = self
+            .client()
+            .sign_raw_hash(self.address, password.clone(), *hash)?;
+        if signature.0.len() != 65 {
+            return Err(errors::account(
+                "Could not sign raw hash.",
+                "invalid signature length",
+            ));
+        }
+        let mut raw = [0u8; 65];
+        raw.copy_from_slice(&signature.0);
+        Ok(raw.into())
     }
 
-    pub fn decode_reader(&self, string: &str) -> Result<Vec<u8>> {
-        let decoded = decode(string).unwrap();
-        Ok(decoded)
+    /// ECIES decryption with the account key, of what the cluster or `encrypt` encrypted to it.
+    pub fn decrypt(&self, password: &Password, data: &[u8]) -> Result<SecretBytes, Error> {
+        self.accounts
+            .decrypt(self.address, Some(password.clone()), &DEFAULT_MAC, data)
+            .map(SecretBytes::from)
+            .map_err(|e| errors::account("Could not decrypt with account.", e))
     }
 
-    pub fn post_to_ipfs(&self, dockey_id: H256, encrypted_secret_document: &str) -> Result<Value> {
-        let filename = &hex::encode(H256::as_bytes(&dockey_id));
-        let mut f = File::create(&filename).unwrap();
-        f.write_all(encrypted_secret_document.as_bytes()).unwrap();
-
-        let client = reqwest::Client::new();
-        let form = reqwest::multipart::Form::new()
-            .file("file", &filename)
-            .unwrap();
-        let mut res = client
-            .post("https://ipfs.infura.io:5001/api/v0/add")
-            .multipart(form)
-            .send()
-            .unwrap();
-        let mut buf: Vec<u8> = vec![];
-        res.copy_to(&mut buf).unwrap();
-        let response = String::from_utf8(buf.to_vec()).unwrap();
-        let v: Value = serde_json::from_str(&response).unwrap();
-        // TODO: ipfs_hash to file
-        Ok(v)
+    pub fn default_client(&self) -> IoHandler<Metadata> {
+        let mut io = IoHandler::default();
+        io.extend_with(self.client().to_delegate());
+        io
     }
+}
 
-    pub fn cat_ipfs(&self, ipfs_uri: &str) -> Result<String> {
-        let param = "https://ipfs.infura.io:5001/api/v0/cat?arg=".to_owned() + &ipfs_uri.to_owned();
-        let mut buf: Vec<u8> = vec![];
-        let mut res = reqwest::get(&param).unwrap();
-        res.copy_to(&mut buf).unwrap();
-        Ok(String::from_utf8(buf.to_vec()).unwrap())
-    }
+fn disk_provider(profile: &Profile) -> AccountProvider {
+    let dir = RootDiskDirectory::create(&profile.keystore_dir).unwrap();
+    let store = ethstore::EthStore::open_with_iterations(Box::new(dir), 1024).unwrap();
+    AccountProvider::new(Box::new(store), AccountProviderSettings::default())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use crate::document_key::EncryptedDocumentKey;
     use ethkey::{verify_public, KeyPair, Signature};
     use jsonrpc_core::Success;
-    use parity_crypto::DEFAULT_MAC;
+    use serde_json::{json, Value};
 
     #[test]
     fn rpc_encrypt_and_decrypt() {
@@ -211,6 +212,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rpc_decrypt_takes_cipher() {
+        let deps = Dependencies::transient();
+        let io = deps.default_client();
+        let secret = "c1f1cfe279a5c350d13795bce162941967340c8a228e6ba175489afc564a5bef"
+            .parse()
+            .unwrap();
+        deps.accounts
+            .insert_account(secret, &"password".into())
+            .unwrap();
+        let key = "0x0440262acc06f1e13cb11b34e792cdf698673a16bb812163cb52689ac34c94ae47047b58f58d8b596d21ac7b03a55896132d07a7dc028b2dad88f6c5a90623fa5b30ff4b1ba385a98c970432d13417cf6d7facd62f86faaef15ca993735890da0cb3e417e2740fc72de7501eef083a12dd5a9ebe513b592b1740848576a936a1eb88fc553fc624b1cae41a0a4e074e34e2aaae686709f08d70e505c5acba12ef96017e89be675a2adb07c72c4e95814fbf";
+        let request = |method: &str, params: Value| {
+            let request = json!({
+                "jsonrpc": "2.0", "method": method, "params": params, "id": 1
+            });
+            let response = io.handle_request_sync(&request.to_string()).unwrap();
+            serde_json::from_str::<Value>(&response).unwrap()
+        };
+        let address = "0x5c2f3b4ec0c2234f8358697edc8b82a62e3ac995";
+
+        let encrypted = request(
+            "secretstore_encrypt",
+            json!([address, "password", key, "0xdeadbeef"]),
+        );
+        let encrypted = encrypted["result"].as_str().unwrap().to_owned();
+        let decrypted = request(
+            "secretstore_decrypt",
+            json!([address, "password", key, encrypted, "aes-256-gcm"]),
+        );
+        assert_eq!(decrypted["result"], "0xdeadbeef");
+
+        // a modified version byte is an error, never a legacy document
+        let modified = format!("0x02{}", &encrypted[4..]);
+        let decrypted = request(
+            "secretstore_decrypt",
+            json!([address, "password", key, modified]),
+        );
+        assert!(decrypted["error"].is_object());
+        let decrypted = request(
+            "secretstore_decrypt",
+            json!([address, "password", key, encrypted, "aes-128-ctr"]),
+        );
+        assert_ne!(decrypted["result"], "0xdeadbeef");
+        let decrypted = request(
+            "secretstore_decrypt",
+            json!([address, "password", key, encrypted, "rot13"]),
+        );
+        assert!(decrypted["error"].is_object());
+    }
+
     #[test]
     fn rpc_sign_raw_hash() {
         let deps = Dependencies::transient();
@@ -240,6 +291,40 @@ mod tests {
         assert!(verify_public(key_pair.public(), &signature, &hash).unwrap());
     }
 
+    #[test]
+    fn signs_without_rpc_request() {
+        let deps = Dependencies::transient();
+        let password = Password::from("");
+        let hash = H256::from_low_u64_be(1);
+        let signature = deps.sign_raw_hash(&password, &hash).unwrap();
+        let public = deps
+            .accounts
+            .account_public(deps.address, &password)
+            .unwrap();
+        assert!(verify_public(&public, &signature, &hash).unwrap());
+
+        let encrypted = ethkey::crypto::ecies::encrypt(&public, &DEFAULT_MAC, b"secret").unwrap();
+        assert_eq!(&*deps.decrypt(&password, &encrypted).unwrap(), b"secret");
+        assert!(deps.decrypt(&"wrong".into(), &encrypted).is_err());
+    }
+
+    #[test]
+    fn multiple_accounts_need_profile_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut profile = Config::default().profile(None, |_| None).unwrap();
+        profile.keystore_dir = dir.path().into();
+        let ap = disk_provider(&profile);
+        ap.new_account(&"first".into()).unwrap();
+        ap.new_account(&"second".into()).unwrap();
+
+        // fails before asking for a password
+        let e = Dependencies::new(&profile).err().unwrap();
+        assert!(e.contains("multiple accounts"));
+        profile.account = Some(H160::from_low_u64_be(1));
+        let e = Dependencies::new(&profile).err().unwrap();
+        assert!(e.contains("not found"));
+    }
+
     #[test]
     fn rpc_generate_document_key() {
         let deps = Dependencies::transient();
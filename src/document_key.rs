@@ -1,6 +1,11 @@
 use crate::bytes::Bytes;
+use crate::errors;
+use crate::helpers::check_point;
 use ethereum_types::H512;
+use jsonrpc_core::Error;
 use serde_derive::{Deserialize, Serialize};
+use std::{fmt, ops::Deref};
+use zeroize::Zeroize;
 
 #[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
 pub struct EncryptedDocumentKey {
@@ -16,9 +21,75 @@ pub struct DecryptedDocumentKey {
     pub decrypt_shadows: Vec<Bytes>,
 }
 
+impl DecryptedDocumentKey {
+    /// Checks a shadow returned by the cluster before it is used. Any `threshold + 1` nodes
+    /// take part in a retrieval, each adding one shadow.
+    pub fn validate(&self, threshold: Option<usize>) -> Result<(), Error> {
+        check_point("decrypted_secret", &self.decrypted_secret)?;
+        check_point("common_point", &self.common_point)?;
+        if self.decrypt_shadows.is_empty() {
+            return Err(errors::invalid_document_key(
+                "decrypt_shadows",
+                "no shadows",
+            ));
+        }
+        if let Some(threshold) = threshold {
+            if self.decrypt_shadows.len() != threshold + 1 {
+                return Err(errors::invalid_document_key(
+                    "decrypt_shadows",
+                    format!(
+                        "{} shadows, threshold {} needs {}",
+                        self.decrypt_shadows.len(),
+                        threshold,
+                        threshold + 1
+                    ),
+                ));
+            }
+        }
+        if let Some(index) = self.decrypt_shadows.iter().position(|s| s.0.is_empty()) {
+            return Err(errors::invalid_document_key(
+                "decrypt_shadows",
+                format!("shadow {} is empty", index),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Plain key material, zeroed when dropped and left out of `Debug` output.
+#[derive(Clone, PartialEq)]
+pub struct SecretBytes(Vec<u8>);
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        SecretBytes(bytes)
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes({} bytes)", self.0.len())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{EncryptedDocumentKey, H512};
+    use super::{DecryptedDocumentKey, EncryptedDocumentKey, SecretBytes, H512};
+    use ethkey::{Generator, Random};
     use serde_json;
 
     #[test]
@@ -40,4 +111,35 @@ mod tests {
         assert_eq!(deserialized.encrypted_point, H512::from_low_u64_be(2));
         assert_eq!(deserialized.encrypted_key, vec![3].into());
     }
+
+    #[test]
+    fn validates_decrypted_document_key() {
+        let point = || H512::from_slice(Random.generate().unwrap().public().as_bytes());
+        let mut key = DecryptedDocumentKey {
+            decrypted_secret: point(),
+            common_point: point(),
+            decrypt_shadows: vec![vec![1].into(), vec![2].into()],
+        };
+        assert!(key.validate(Some(1)).is_ok());
+        assert!(key.validate(None).is_ok());
+        assert!(key.validate(Some(2)).is_err());
+
+        key.decrypt_shadows.push(vec![].into());
+        assert!(key.validate(None).is_err());
+        key.decrypt_shadows.clear();
+        assert!(key.validate(None).is_err());
+
+        key.decrypt_shadows.push(vec![1].into());
+        key.common_point = H512::zero();
+        assert!(key.validate(None).is_err());
+        key.common_point = H512::from_low_u64_be(1);
+        assert!(key.validate(None).is_err());
+    }
+
+    #[test]
+    fn secret_bytes_are_not_printed() {
+        let key = SecretBytes::from(vec![0xab; 64]);
+        assert_eq!(format!("{:?}", key), "SecretBytes(64 bytes)");
+        assert_eq!(&key[..2], &[0xab, 0xab]);
+    }
 }
@@ -0,0 +1,350 @@
+//! EIP-712 typed structured data hashing.
+//!
+//! Typed data is given in the JSON form of `eth_signTypedData`:
+//!
+//! ```json
+//! {
+//!   "types": { "EIP712Domain": [...], "Mail": [{ "name": "contents", "type": "string" }] },
+//!   "primaryType": "Mail",
+//!   "domain": { ... },
+//!   "message": { ... }
+//! }
+//! ```
+//!
+//! Atomic types, `string`, `bytes`, structs and arrays of any of them are supported.
+
+use std::{collections::BTreeMap, collections::BTreeSet, error, fmt};
+
+use ethereum_types::{H256, U256};
+use serde_json::Value;
+use tiny_keccak::keccak256;
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<Field>>,
+    #[serde(rename = "primaryType")]
+    pub primary_type: String,
+    pub domain: Value,
+    pub message: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownType(String),
+    MissingField(String),
+    /// Value that does not fit its type.
+    InvalidValue {
+        kind: String,
+        value: Value,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownType(ref kind) => write!(f, "unknown type {}", kind),
+            Error::MissingField(ref name) => write!(f, "missing field {}", name),
+            Error::InvalidValue {
+                ref kind,
+                ref value,
+            } => write!(f, "{} is not a valid {}", value, kind),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl TypedData {
+    /// `keccak256(0x19 0x01 || domainSeparator || hashStruct(message))`, the hash that is signed.
+    pub fn hash(&self) -> Result<H256, Error> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(self.domain_separator()?.as_bytes());
+        encoded.extend_from_slice(
+            self.hash_struct(&self.primary_type, &self.message)?
+                .as_bytes(),
+        );
+        Ok(H256(keccak256(&encoded)))
+    }
+
+    pub fn domain_separator(&self) -> Result<H256, Error> {
+        self.hash_struct(DOMAIN_TYPE, &self.domain)
+    }
+
+    /// `keccak256(typeHash || encodeData(value))`.
+    pub fn hash_struct(&self, kind: &str, value: &Value) -> Result<H256, Error> {
+        let fields = self.fields(kind)?;
+        let mut encoded = keccak256(self.encode_type(kind)?.as_bytes()).to_vec();
+        for field in fields {
+            let member = value
+                .get(&field.name)
+                .ok_or_else(|| Error::MissingField(format!("{}.{}", kind, field.name)))?;
+            encoded.extend_from_slice(&self.encode_value(&field.kind, member)?);
+        }
+        Ok(H256(keccak256(&encoded)))
+    }
+
+    /// `Mail(Person from,Person to,string contents)Person(string name,address wallet)`: the
+    /// struct followed by the structs it references, sorted by name.
+    pub fn encode_type(&self, kind: &str) -> Result<String, Error> {
+        let mut referenced = BTreeSet::new();
+        self.collect_references(kind, &mut referenced)?;
+        referenced.remove(kind);
+        let mut encoded = String::new();
+        for name in Some(kind)
+            .into_iter()
+            .chain(referenced.iter().map(String::as_str))
+        {
+            let members: Vec<_> = self
+                .fields(name)?
+                .iter()
+                .map(|field| format!("{} {}", field.kind, field.name))
+                .collect();
+            encoded.push_str(&format!("{}({})", name, members.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn fields(&self, kind: &str) -> Result<&[Field], Error> {
+        self.types
+            .get(kind)
+            .map(Vec::as_slice)
+            .ok_or_else(|| Error::UnknownType(kind.into()))
+    }
+
+    fn collect_references(
+        &self,
+        kind: &str,
+        referenced: &mut BTreeSet<String>,
+    ) -> Result<(), Error> {
+        let kind = element_type(kind).unwrap_or(kind);
+        if !self.types.contains_key(kind) || !referenced.insert(kind.into()) {
+            return Ok(());
+        }
+        for field in self.fields(kind)? {
+            self.collect_references(&field.kind, referenced)?;
+        }
+        Ok(())
+    }
+
+    /// 32 byte encoding of a member, dynamic values and structs are hashed.
+    fn encode_value(&self, kind: &str, value: &Value) -> Result<[u8; 32], Error> {
+        let invalid = || Error::InvalidValue {
+            kind: kind.into(),
+            value: value.clone(),
+        };
+        if let Some(element) = element_type(kind) {
+            let items = value.as_array().ok_or_else(invalid)?;
+            if let Some(len) = array_len(kind) {
+                if items.len() != len {
+                    return Err(invalid());
+                }
+            }
+            let mut encoded = Vec::with_capacity(items.len() * 32);
+            for item in items {
+                encoded.extend_from_slice(&self.encode_value(element, item)?);
+            }
+            return Ok(keccak256(&encoded));
+        }
+        if self.types.contains_key(kind) {
+            return Ok(self.hash_struct(kind, value)?.0);
+        }
+        let mut word = [0u8; 32];
+        match kind {
+            "string" => word = keccak256(value.as_str().ok_or_else(invalid)?.as_bytes()),
+            "bytes" => word = keccak256(&hex_bytes(value).ok_or_else(invalid)?),
+            "bool" => word[31] = value.as_bool().ok_or_else(invalid)? as u8,
+            "address" => {
+                let address = hex_bytes(value)
+                    .filter(|a| a.len() == 20)
+                    .ok_or_else(invalid)?;
+                word[12..].copy_from_slice(&address);
+            }
+            _ if kind.starts_with("bytes") => {
+                let len = type_size(kind, "bytes").filter(|&len| len >= 1 && len <= 32);
+                let bytes = hex_bytes(value).ok_or_else(invalid)?;
+                if len != Some(bytes.len()) {
+                    return Err(invalid());
+                }
+                word[..bytes.len()].copy_from_slice(&bytes);
+            }
+            _ if kind.starts_with("uint") => {
+                let bits = int_bits(kind, "uint").ok_or_else(|| Error::UnknownType(kind.into()))?;
+                let (negative, magnitude) = integer(value).ok_or_else(invalid)?;
+                if negative || magnitude.bits() > bits {
+                    return Err(invalid());
+                }
+                magnitude.to_big_endian(&mut word);
+            }
+            _ if kind.starts_with("int") => {
+                let bits = int_bits(kind, "int").ok_or_else(|| Error::UnknownType(kind.into()))?;
+                let (negative, magnitude) = integer(value).ok_or_else(invalid)?;
+                // -2^(bits - 1) is the only value needing all bits
+                let min = U256::one() << (bits - 1);
+                if magnitude.bits() >= bits && !(negative && magnitude == min) {
+                    return Err(invalid());
+                }
+                let encoded = if negative {
+                    (!magnitude).overflowing_add(U256::one()).0
+                } else {
+                    magnitude
+                };
+                encoded.to_big_endian(&mut word);
+            }
+            _ => return Err(Error::UnknownType(kind.into())),
+        }
+        Ok(word)
+    }
+}
+
+/// Element type of `T[]` and `T[n]`.
+fn element_type(kind: &str) -> Option<&str> {
+    if kind.ends_with(']') {
+        kind.rfind('[').map(|start| &kind[..start])
+    } else {
+        None
+    }
+}
+
+fn array_len(kind: &str) -> Option<usize> {
+    let start = kind.rfind('[')?;
+    kind[start + 1..kind.len() - 1].parse().ok()
+}
+
+fn type_size(kind: &str, prefix: &str) -> Option<usize> {
+    kind[prefix.len()..].parse().ok()
+}
+
+/// Bits of `uintN` and `intN`, a multiple of 8 up to 256.
+fn int_bits(kind: &str, prefix: &str) -> Option<usize> {
+    type_size(kind, prefix).filter(|&bits| bits > 0 && bits <= 256 && bits % 8 == 0)
+}
+
+fn hex_bytes(value: &Value) -> Option<Vec<u8>> {
+    let hex = value.as_str()?;
+    if !hex.starts_with("0x") {
+        return None;
+    }
+    hex::decode(&hex[2..]).ok()
+}
+
+/// Sign and magnitude of a JSON number, or of a decimal or `0x` hex string.
+fn integer(value: &Value) -> Option<(bool, U256)> {
+    match *value {
+        Value::Number(ref number) => match (number.as_u64(), number.as_i64()) {
+            (Some(n), _) => Some((false, U256::from(n))),
+            (None, Some(n)) => Some((true, U256::from(n.wrapping_neg() as u64))),
+            _ => None,
+        },
+        Value::String(ref s) => {
+            let (negative, digits) = match s.starts_with('-') {
+                true => (true, &s[1..]),
+                false => (false, &s[..]),
+            };
+            let magnitude = if digits.starts_with("0x") {
+                digits[2..].parse().ok()?
+            } else {
+                U256::from_dec_str(digits).ok()?
+            };
+            Some((negative && !magnitude.is_zero(), magnitude))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example of the EIP.
+    fn mail() -> TypedData {
+        serde_json::from_str(
+            r#"{
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "Person": [
+                    { "name": "name", "type": "string" },
+                    { "name": "wallet", "type": "address" }
+                ],
+                "Mail": [
+                    { "name": "from", "type": "Person" },
+                    { "name": "to", "type": "Person" },
+                    { "name": "contents", "type": "string" }
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+                "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+                "contents": "Hello, Bob!"
+            }
+        }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn hashes_eip_example() {
+        let mail = mail();
+        assert_eq!(
+            mail.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            format!("{:x}", mail.domain_separator().unwrap()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            format!("{:x}", mail.hash_struct("Mail", &mail.message).unwrap()),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            format!("{:x}", mail.hash().unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut mail = mail();
+        mail.message["to"]["wallet"] = "0xbb".into();
+        assert!(mail.hash().is_err());
+        mail.message["to"].as_object_mut().unwrap().remove("wallet");
+        assert_eq!(
+            mail.hash(),
+            Err(Error::MissingField("Person.wallet".into()))
+        );
+        mail.primary_type = "Letter".into();
+        assert_eq!(mail.hash(), Err(Error::UnknownType("Letter".into())));
+
+        let data = mail();
+        let word = |kind: &str, value: Value| data.encode_value(kind, &value);
+        assert_eq!(word("int8", (-1).into()).unwrap(), [0xff; 32]);
+        assert!(word("int8", (-128).into()).is_ok());
+        assert!(word("int8", 128.into()).is_err());
+        assert!(word("uint8", 256.into()).is_err());
+        assert!(word("uint256", "-1".into()).is_err());
+        assert_eq!(word("uint16", "0x0102".into()).unwrap()[30..], [1, 2]);
+        assert!(word("bytes2", "0x010203".into()).is_err());
+        assert!(word("uint7", 1.into()).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! Binary container of an encrypted document.
+//!
+//! ```text
+//! magic "SSCE" | version u8 | cipher u8 | threshold u32 | dockey_id [32]
+//! | servers_set_hash [32] | size u64 | plaintext_hash [32] | recipients u16
+//! | (public [64] | encrypted key length u16 | encrypted key)* | ciphertext
+//! ```
+//!
+//! Recipients are accounts the document key was ECIES encrypted to at encryption, so that they
+//! can decrypt without the cluster. The only cipher is 1, `Cipher::Aes256Gcm`.
+//!
+//! Large documents are split into chunks stored on their own, each chunk being the
+//! ciphertext of up to `chunk_size` plaintext bytes. A manifest lists them in order:
+//!
+//! ```text
+//! magic "SSCM" | version u8 | header as above | chunk_size u32 | chunks u32
+//! | (location length u16 | location)*
+//! ```
+//!
+//! Chunks are encrypted with `helpers::encrypt_chunk`, which authenticates their index and
+//! whether they are the last one.
+//!
+//! Integers are big endian. Documents written before the envelope existed are the quoted hex
+//! string returned by `secretstore_encrypt` over a base64 copy of the file, encrypted with
+//! `Cipher::Aes128Ctr`.
+
+use std::{error, fmt};
+
+use ethereum_types::{H256, H512};
+use parity_bytes::Bytes;
+use ring::digest;
+
+use crate::helpers;
+
+pub const MAGIC: &[u8; 4] = b"SSCE";
+pub const MANIFEST_MAGIC: &[u8; 4] = b"SSCM";
+pub const VERSION: u8 = 1;
+pub const MANIFEST_VERSION: u8 = 1;
+/// Id of `Cipher::Aes256Gcm` in the header.
+const CIPHER_ID: u8 = 1;
+/// Without recipients.
+const HEADER_LEN: usize = 4 + 1 + 1 + 4 + 32 + 32 + 8 + 32 + 2;
+
+/// Named `aes-128-ctr` and `aes-256-gcm` in the `cipher` parameter of the decrypt RPCs.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Cipher {
+    /// AES-128-CTR under the first 16 bytes of the document key, the 16 byte iv follows the
+    /// ciphertext. Only read from legacy documents, it is not authenticated.
+    #[serde(rename = "aes-128-ctr")]
+    Aes128Ctr,
+    /// `secretstore_encrypt`: AES-256-GCM under a key derived from the document key. The key
+    /// derivation version comes first, the tag and the 12 byte nonce follow the ciphertext.
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl Cipher {
+    /// Decrypts `ciphertext` under the 64 byte document key.
+    pub fn decrypt(self, key: &[u8], ciphertext: Bytes) -> Result<Bytes, jsonrpc_core::Error> {
+        match self {
+            Cipher::Aes128Ctr => helpers::decrypt_legacy_document(key, ciphertext),
+            Cipher::Aes256Gcm => helpers::decrypt_document(key, ciphertext),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub threshold: u32,
+    pub dockey_id: H256,
+    /// `secretstore_serversSetHash` of the cluster, zero when unknown.
+    pub servers_set_hash: H256,
+    /// Length of the plaintext.
+    pub size: u64,
+    /// sha256 of the plaintext.
+    pub plaintext_hash: H256,
+    pub recipients: Vec<WrappedKey>,
+}
+
+/// Document key encrypted to a recipient, as `secretstore_decrypt` expects it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrappedKey {
+    pub public: H512,
+    /// ECIES ciphertext of the 64 byte document key.
+    pub encrypted_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub header: Header,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Chunked document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub header: Header,
+    pub chunk_size: u32,
+    /// Storage locations of the chunks, in plaintext order.
+    pub chunks: Vec<String>,
+}
+
+/// What a storage location holds.
+#[derive(Debug, PartialEq)]
+pub enum Document {
+    Envelope(Envelope),
+    Manifest(Manifest),
+    /// Hex string of the ciphertext, without quotes.
+    Legacy(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownCipher(u8),
+    /// Neither an envelope nor a legacy hex string.
+    UnknownFormat,
+    /// Decrypted document does not hash to the one that was encrypted.
+    PlaintextMismatch {
+        expected: H256,
+        actual: H256,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Truncated => write!(f, "encrypted document is truncated"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported envelope version {}", version)
+            }
+            Error::UnknownCipher(id) => write!(f, "unknown cipher {}", id),
+            Error::UnknownFormat => write!(f, "not an encrypted document"),
+            Error::PlaintextMismatch { expected, actual } => write!(
+                f,
+                "decrypted document hashes to {:?} instead of {:?}, \
+                 the document key or the encrypted document is wrong",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Header {
+    /// Magic and version followed by the header fields.
+    fn encode(&self, magic: &[u8; 4], version: u8, encoded: &mut Vec<u8>) {
+        encoded.extend_from_slice(magic);
+        encoded.push(version);
+        encoded.push(CIPHER_ID);
+        encoded.extend_from_slice(&self.threshold.to_be_bytes());
+        encoded.extend_from_slice(self.dockey_id.as_bytes());
+        encoded.extend_from_slice(self.servers_set_hash.as_bytes());
+        encoded.extend_from_slice(&self.size.to_be_bytes());
+        encoded.extend_from_slice(self.plaintext_hash.as_bytes());
+        encoded.extend_from_slice(&(self.recipients.len() as u16).to_be_bytes());
+        for recipient in &self.recipients {
+            encoded.extend_from_slice(recipient.public.as_bytes());
+            encoded.extend_from_slice(&(recipient.encrypted_key.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(&recipient.encrypted_key);
+        }
+    }
+
+    /// Key wrapped for `public`, if it is a recipient.
+    pub fn wrapped_key(&self, public: &H512) -> Option<&[u8]> {
+        self.recipients
+            .iter()
+            .find(|recipient| recipient.public == *public)
+            .map(|recipient| &recipient.encrypted_key[..])
+    }
+
+    fn decode(magic: &[u8; 4], version: u8, reader: &mut Reader) -> Result<Self, Error> {
+        if reader.take(magic.len())? != magic {
+            return Err(Error::UnknownFormat);
+        }
+        let read_version = reader.u8()?;
+        if read_version != version {
+            return Err(Error::UnsupportedVersion(read_version));
+        }
+        let cipher = reader.u8()?;
+        if cipher != CIPHER_ID {
+            return Err(Error::UnknownCipher(cipher));
+        }
+        let mut header = Header {
+            threshold: reader.u32()?,
+            dockey_id: reader.h256()?,
+            servers_set_hash: reader.h256()?,
+            size: reader.u64()?,
+            plaintext_hash: reader.h256()?,
+            recipients: Vec::new(),
+        };
+        for _ in 0..reader.u16()? {
+            let public = H512::from_slice(reader.take(64)?);
+            let len = reader.u16()? as usize;
+            header.recipients.push(WrappedKey {
+                public,
+                encrypted_key: reader.take(len)?.to_vec(),
+            });
+        }
+        Ok(header)
+    }
+}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER_LEN + self.ciphertext.len());
+        self.header.encode(MAGIC, VERSION, &mut encoded);
+        encoded.extend_from_slice(&self.ciphertext);
+        encoded
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(data);
+        let header = Header::decode(MAGIC, VERSION, &mut reader)?;
+        Ok(Envelope {
+            header,
+            ciphertext: reader.0.to_vec(),
+        })
+    }
+}
+
+impl Manifest {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER_LEN + 8 + self.chunks.len() * 66);
+        self.header
+            .encode(MANIFEST_MAGIC, MANIFEST_VERSION, &mut encoded);
+        encoded.extend_from_slice(&self.chunk_size.to_be_bytes());
+        encoded.extend_from_slice(&(self.chunks.len() as u32).to_be_bytes());
+        for chunk in &self.chunks {
+            encoded.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(chunk.as_bytes());
+        }
+        encoded
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(data);
+        let header = Header::decode(MANIFEST_MAGIC, MANIFEST_VERSION, &mut reader)?;
+        let chunk_size = reader.u32()?;
+        let count = reader.u32()?;
+        let mut chunks = Vec::new();
+        for _ in 0..count {
+            let len = reader.u16()? as usize;
+            let location =
+                String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| Error::UnknownFormat)?;
+            chunks.push(location);
+        }
+        Ok(Manifest {
+            header,
+            chunk_size,
+            chunks,
+        })
+    }
+}
+
+/// sha256 the plaintext hash of a header is computed with.
+pub fn plaintext_hash(plaintext: &[u8]) -> H256 {
+    H256::from_slice(digest::digest(&digest::SHA256, plaintext).as_ref())
+}
+
+pub fn check_plaintext_hash(expected: H256, actual: H256) -> Result<(), Error> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::PlaintextMismatch { expected, actual })
+    }
+}
+
+/// Tells envelopes from legacy documents.
+pub fn parse(data: &[u8]) -> Result<Document, Error> {
+    if data.starts_with(MAGIC) {
+        return Envelope::decode(data).map(Document::Envelope);
+    }
+    if data.starts_with(MANIFEST_MAGIC) {
+        return Manifest::decode(data).map(Document::Manifest);
+    }
+    let legacy = String::from_utf8_lossy(data);
+    let hex = legacy.trim().trim_matches('"');
+    let digits = hex.trim_start_matches("0x");
+    if hex.starts_with("0x") && digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+        Ok(Document::Legacy(hex.into()))
+    } else {
+        Err(Error::UnknownFormat)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn h256(&mut self) -> Result<H256, Error> {
+        Ok(H256::from_slice(self.take(32)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope() -> Envelope {
+        Envelope {
+            header: Header {
+                threshold: 1,
+                dockey_id: H256::from_low_u64_be(1),
+                servers_set_hash: H256::from_low_u64_be(2),
+                size: 13,
+                plaintext_hash: H256::from_low_u64_be(1),
+                recipients: Vec::new(),
+            },
+            ciphertext: vec![0xde, 0xad, 0xbe, 0xef],
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let encoded = envelope().encode();
+        assert_eq!(encoded.len(), HEADER_LEN + 4);
+        assert_eq!(&encoded[..6], b"SSCE\x01\x01");
+        assert_eq!(parse(&encoded).unwrap(), Document::Envelope(envelope()));
+
+        assert_eq!(
+            Envelope::decode(&encoded[..HEADER_LEN - 1]),
+            Err(Error::Truncated)
+        );
+        let mut unknown = encoded.clone();
+        unknown[4] = 9;
+        assert_eq!(
+            Envelope::decode(&unknown),
+            Err(Error::UnsupportedVersion(9))
+        );
+        unknown[4] = 2;
+        assert_eq!(
+            Envelope::decode(&unknown),
+            Err(Error::UnsupportedVersion(2))
+        );
+        unknown[4] = VERSION;
+        // no other cipher, AES-128-CTR included, is read from an envelope
+        unknown[5] = 2;
+        assert_eq!(Envelope::decode(&unknown), Err(Error::UnknownCipher(2)));
+    }
+
+    #[test]
+    fn encode_and_decode_recipients() {
+        let mut envelope = envelope();
+        envelope.header.recipients = vec![
+            WrappedKey {
+                public: H512::from_low_u64_be(3),
+                encrypted_key: vec![1, 2, 3],
+            },
+            WrappedKey {
+                public: H512::from_low_u64_be(4),
+                encrypted_key: vec![4],
+            },
+        ];
+        let encoded = envelope.encode();
+        assert_eq!(encoded.len(), HEADER_LEN + 2 * (64 + 2) + 4 + 4);
+        let decoded = Envelope::decode(&encoded).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(
+            decoded.header.wrapped_key(&H512::from_low_u64_be(4)),
+            Some(&[4u8][..])
+        );
+        assert_eq!(decoded.header.wrapped_key(&H512::zero()), None);
+    }
+
+    #[test]
+    fn encode_and_decode_manifest() {
+        let manifest = Manifest {
+            header: envelope().header,
+            chunk_size: 4,
+            chunks: vec!["QmA".into(), "QmB".into(), "QmC".into(), "QmD".into()],
+        };
+        let encoded = manifest.encode();
+        assert_eq!(&encoded[..5], b"SSCM\x01");
+        assert_eq!(parse(&encoded).unwrap(), Document::Manifest(manifest));
+        assert_eq!(
+            Manifest::decode(&encoded[..encoded.len() - 1]),
+            Err(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn checks_plaintext_hash() {
+        let hash = plaintext_hash(b"Hello, world!");
+        assert_eq!(
+            format!("{:x}", hash),
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3"
+        );
+        assert!(check_plaintext_hash(hash, hash).is_ok());
+        assert_eq!(
+            check_plaintext_hash(H256::zero(), hash),
+            Err(Error::PlaintextMismatch {
+                expected: H256::zero(),
+                actual: hash,
+            })
+        );
+    }
+
+    #[test]
+    fn reads_legacy_documents() {
+        assert_eq!(
+            parse(b"\"0xb6fa6a9a\"").unwrap(),
+            Document::Legacy("0xb6fa6a9a".into())
+        );
+        assert_eq!(parse(b"hello"), Err(Error::UnknownFormat));
+    }
+}
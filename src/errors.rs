@@ -4,6 +4,8 @@ use std::fmt;
 mod codes {
     pub const ACCOUNT_ERROR: i64 = -32023;
     pub const ENCRYPTION_ERROR: i64 = -32055;
+    pub const AUTHENTICATION_ERROR: i64 = -32056;
+    pub const DOCUMENT_KEY_ERROR: i64 = -32057;
 }
 
 pub fn account<T: fmt::Debug>(error: &str, details: T) -> Error {
@@ -22,6 +24,26 @@ pub fn encryption<T: fmt::Debug>(error: T) -> Error {
     }
 }
 
+/// The authentication tag of an encrypted document did not verify.
+pub fn authentication() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::AUTHENTICATION_ERROR),
+        message: "Document authentication failed.".into(),
+        data: Some(Value::String(
+            "the encrypted document was modified or the document key is wrong".into(),
+        )),
+    }
+}
+
+/// A document key shadow, as returned by the cluster, that can not be used.
+pub fn invalid_document_key<T: fmt::Debug>(field: &str, details: T) -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::DOCUMENT_KEY_ERROR),
+        message: format!("Invalid document key shadow: {}", field),
+        data: Some(Value::String(format!("{:?}", details))),
+    }
+}
+
 pub fn invalid_params<T: fmt::Debug>(param: &str, details: T) -> Error {
     Error {
         code: ErrorCode::InvalidParams,
@@ -1,4 +1,4 @@
-use crate::document_key::EncryptedDocumentKey;
+use crate::document_key::{EncryptedDocumentKey, SecretBytes};
 use crate::errors;
 use ethereum_types::{H256, H512};
 use ethkey::{self, math, Generator, Public, Random, Secret};
@@ -7,10 +7,66 @@ use parity_bytes::Bytes;
 use parity_crypto as crypto;
 use rand::rngs::OsRng;
 use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf;
 use std::collections::BTreeSet;
+use std::fmt;
 use tiny_keccak::Keccak;
+use zeroize::Zeroize;
 
 const INIT_VEC_LEN: usize = 16;
+/// Version of the document key derivation, the first byte of encrypted documents.
+pub const KDF_VERSION: u8 = 1;
+const KDF_SALT: &[u8] = b"secret-store-cli document key v1";
+/// Bytes an encrypted document has on top of the document: kdf version, tag and nonce.
+pub const AEAD_OVERHEAD: usize = 1 + 16 + NONCE_LEN;
+
+/// Keys derived from the document key point, one per purpose so that none is used twice.
+/// Zeroed when dropped.
+pub struct DocumentKeys {
+    /// AES-256-GCM key of the document.
+    pub encryption: [u8; 32],
+    /// For authenticating data stored next to the document.
+    pub mac: [u8; 32],
+    /// For encrypting data stored next to the document.
+    pub metadata: [u8; 32],
+}
+
+impl DocumentKeys {
+    /// HKDF-SHA256 over the whole 64 byte point, expanded with a label per key.
+    pub fn derive(key: &[u8]) -> Result<Self, Error> {
+        if key.len() != 64 {
+            return Err(errors::invalid_params("key", "invalid public key length"));
+        }
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, KDF_SALT).extract(key);
+        let expand = |label: &[u8]| -> Result<[u8; 32], Error> {
+            let mut derived = [0u8; 32];
+            prk.expand(&[label], hkdf::HKDF_SHA256)
+                .and_then(|okm| okm.fill(&mut derived))
+                .map_err(errors::encryption)?;
+            Ok(derived)
+        };
+        Ok(DocumentKeys {
+            encryption: expand(b"encryption")?,
+            mac: expand(b"mac")?,
+            metadata: expand(b"metadata")?,
+        })
+    }
+}
+
+impl Drop for DocumentKeys {
+    fn drop(&mut self) {
+        self.encryption.zeroize();
+        self.mac.zeroize();
+        self.metadata.zeroize();
+    }
+}
+
+impl fmt::Debug for DocumentKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DocumentKeys(..)")
+    }
+}
 
 pub fn generate_document_key(
     account_public: Public,
@@ -19,12 +75,7 @@ pub fn generate_document_key(
     let document_key = Random.generate().map_err(errors::encryption)?;
     let (common_point, encrypted_point) =
         encrypt_secret(document_key.public(), &server_key_public)?;
-    let encrypted_key = ethkey::crypto::ecies::encrypt(
-        &account_public,
-        &crypto::DEFAULT_MAC,
-        document_key.public().as_bytes(),
-    )
-    .map_err(errors::encryption)?;
+    let encrypted_key = wrap_document_key(document_key.public().as_bytes(), &account_public)?;
 
     Ok(EncryptedDocumentKey {
         common_point: common_point.into(),
@@ -33,20 +84,94 @@ pub fn generate_document_key(
     })
 }
 
-pub fn encrypt_document(key: Bytes, document: Bytes) -> Result<Bytes, Error> {
-    let key = into_document_key(key)?;
-    let iv = initialization_vector();
-    let mut encrypted_document = vec![0; document.len() + iv.len()];
-    {
-        let (mut encryption_buffer, iv_buffer) = encrypted_document.split_at_mut(document.len());
-        crypto::aes::encrypt_128_ctr(&key, &iv, &document, &mut encryption_buffer)
-            .map_err(errors::encryption)?;
-        iv_buffer.copy_from_slice(&iv);
+/// ECIES encrypts the document key to `recipient`, which can pass it to `secretstore_decrypt`.
+pub fn wrap_document_key(key: &[u8], recipient: &Public) -> Result<Bytes, Error> {
+    ethkey::crypto::ecies::encrypt(recipient, &crypto::DEFAULT_MAC, key).map_err(errors::encryption)
+}
+
+/// AES-256-GCM under the derived encryption key. The kdf version comes first, authenticated as
+/// associated data, the tag and the 12 byte nonce follow the ciphertext.
+pub fn encrypt_document(key: &[u8], document: Bytes) -> Result<Bytes, Error> {
+    seal(key, document, &[])
+}
+
+/// Fails with `errors::authentication` when the document or the key is not the one encrypted.
+pub fn decrypt_document(key: &[u8], encrypted_document: Bytes) -> Result<Bytes, Error> {
+    open(key, encrypted_document, &[])
+}
+
+/// Like `encrypt_document`, also authenticating the position of the chunk in its document and
+/// whether it is the last one, so chunks cannot be reordered, dropped or appended.
+pub fn encrypt_chunk(key: &[u8], chunk: Bytes, index: u64, last: bool) -> Result<Bytes, Error> {
+    seal(key, chunk, &chunk_context(index, last))
+}
+
+pub fn decrypt_chunk(
+    key: &[u8],
+    encrypted_chunk: Bytes,
+    index: u64,
+    last: bool,
+) -> Result<Bytes, Error> {
+    open(key, encrypted_chunk, &chunk_context(index, last))
+}
+
+/// Chunk index and final-chunk flag.
+fn chunk_context(index: u64, last: bool) -> [u8; 9] {
+    let mut context = [0u8; 9];
+    context[..8].copy_from_slice(&index.to_be_bytes());
+    context[8] = last as u8;
+    context
+}
+
+/// Associated data is the kdf version followed by `context`.
+fn seal(key: &[u8], document: Bytes, context: &[u8]) -> Result<Bytes, Error> {
+    let key = aead_key(key, KDF_VERSION)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut encrypted_document = Vec::with_capacity(document.len() + AEAD_OVERHEAD);
+    encrypted_document.push(KDF_VERSION);
+    encrypted_document.extend_from_slice(&document);
+    let tag = key
+        .seal_in_place_separate_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from([&[KDF_VERSION][..], context].concat()),
+            &mut encrypted_document[1..],
+        )
+        .map_err(errors::encryption)?;
+    encrypted_document.extend_from_slice(tag.as_ref());
+    encrypted_document.extend_from_slice(&nonce);
+    Ok(encrypted_document)
+}
+
+fn open(key: &[u8], mut encrypted_document: Bytes, context: &[u8]) -> Result<Bytes, Error> {
+    let encrypted_document_len = encrypted_document.len();
+    if encrypted_document_len < AEAD_OVERHEAD {
+        return Err(errors::invalid_params(
+            "encryted_document",
+            "invalied encrypted data",
+        ));
     }
+    let version = encrypted_document[0];
+    let key = aead_key(key, version)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&encrypted_document[encrypted_document_len - NONCE_LEN..]);
+    encrypted_document.truncate(encrypted_document_len - NONCE_LEN);
+    let document_len = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from([&[version][..], context].concat()),
+            &mut encrypted_document[1..],
+        )
+        .map_err(|_| errors::authentication())?
+        .len();
+    encrypted_document.truncate(1 + document_len);
+    encrypted_document.remove(0);
     Ok(encrypted_document)
 }
 
-pub fn decrypt_document(key: Bytes, mut encrypted_document: Bytes) -> Result<Bytes, Error> {
+/// AES-128-CTR documents written before `encrypt_document` was authenticated, a wrong key or a
+/// modified document decrypts to garbage instead of failing.
+pub fn decrypt_legacy_document(key: &[u8], mut encrypted_document: Bytes) -> Result<Bytes, Error> {
     let encrypted_document_len = encrypted_document.len();
     if encrypted_document_len < INIT_VEC_LEN {
         return Err(errors::invalid_params(
@@ -70,15 +195,21 @@ pub fn decrypt_document_with_shadow(
     encrypted_document: Bytes,
 ) -> Result<Bytes, Error> {
     let key = decrypt_with_shadow_coefficients(decrypted_secret, common_point, shadows)?;
-    decrypt_document(key.as_bytes().to_vec(), encrypted_document)
+    decrypt_document(&key, encrypted_document)
 }
 
+/// Document key point, 64 bytes.
 pub fn decrypt_with_shadow_coefficients(
     mut decrypted_shadow: Public,
     mut common_shadow_point: Public,
     shadow_coefficients: Vec<Secret>,
-) -> Result<Public, Error> {
-    let mut shadow_coefficients_sum = shadow_coefficients[0].clone();
+) -> Result<SecretBytes, Error> {
+    check_point("decrypted_secret", &decrypted_shadow)?;
+    check_point("common_point", &common_shadow_point)?;
+    let mut shadow_coefficients_sum = shadow_coefficients
+        .first()
+        .cloned()
+        .ok_or_else(|| errors::invalid_document_key("decrypt_shadows", "no shadows"))?;
     for shadow_coefficients in shadow_coefficients.iter().skip(1) {
         shadow_coefficients_sum
             .add(shadow_coefficients)
@@ -87,7 +218,21 @@ pub fn decrypt_with_shadow_coefficients(
     math::public_mul_secret(&mut common_shadow_point, &shadow_coefficients_sum)
         .map_err(errors::encryption)?;
     math::public_add(&mut decrypted_shadow, &common_shadow_point).map_err(errors::encryption)?;
-    Ok(decrypted_shadow)
+    let key = SecretBytes::from(decrypted_shadow.as_bytes().to_vec());
+    decrypted_shadow.as_bytes_mut().zeroize();
+    Ok(key)
+}
+
+/// Points from the cluster must be on secp256k1, the identity has no encoding and fails too.
+pub fn check_point(field: &str, point: &Public) -> Result<(), Error> {
+    if math::public_is_valid(point) {
+        Ok(())
+    } else {
+        Err(errors::invalid_document_key(
+            field,
+            "not a point on secp256k1",
+        ))
+    }
 }
 
 pub fn ordered_servers_keccak(servers_set: BTreeSet<H512>) -> H256 {
@@ -100,17 +245,33 @@ pub fn ordered_servers_keccak(servers_set: BTreeSet<H512>) -> H256 {
     servers_set_keccak_value.into()
 }
 
-fn into_document_key(key: Bytes) -> Result<Bytes, Error> {
+/// EIP-191 hash of `personal_sign`, `keccak256("\x19Ethereum Signed Message:\n" || len || message)`.
+pub fn personal_message_hash(message: &[u8]) -> H256 {
+    let mut keccak = Keccak::new_keccak256();
+    keccak.update(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    keccak.update(message);
+    let mut hash = [0u8; 32];
+    keccak.finalize(&mut hash);
+    hash.into()
+}
+
+fn into_document_key(key: &[u8]) -> Result<SecretBytes, Error> {
     if key.len() != 64 {
         return Err(errors::invalid_params("key", "invalid public key length"));
     }
-    Ok(key[..INIT_VEC_LEN].into())
+    Ok(key[..INIT_VEC_LEN].to_vec().into())
 }
 
-fn initialization_vector() -> [u8; INIT_VEC_LEN] {
-    let mut result = [0u8; INIT_VEC_LEN];
-    OsRng.fill_bytes(&mut result);
-    result
+fn aead_key(key: &[u8], kdf_version: u8) -> Result<LessSafeKey, Error> {
+    if kdf_version != KDF_VERSION {
+        return Err(errors::invalid_params(
+            "encrypted_document",
+            format!("unsupported key derivation version {}", kdf_version),
+        ));
+    }
+    let keys = DocumentKeys::derive(key)?;
+    let key = UnboundKey::new(&AES_256_GCM, &keys.encryption).map_err(errors::encryption)?;
+    Ok(LessSafeKey::new(key))
 }
 
 fn encrypt_secret(secret: &Public, joint_public: &Public) -> Result<(Public, Public), Error> {
@@ -126,7 +287,13 @@ fn encrypt_secret(secret: &Public, joint_public: &Public) -> Result<(Public, Pub
 
 #[cfg(test)]
 mod tests {
-    use super::{decrypt_document, decrypt_document_with_shadow, encrypt_document};
+    use super::{
+        decrypt_chunk, decrypt_document, decrypt_document_with_shadow, decrypt_legacy_document,
+        decrypt_with_shadow_coefficients, encrypt_chunk, encrypt_document, personal_message_hash,
+        DocumentKeys, AEAD_OVERHEAD, KDF_VERSION,
+    };
+    use crate::errors;
+    use ethereum_types::H512;
     use parity_bytes::Bytes;
     use rustc_hex::FromHex;
 
@@ -134,16 +301,92 @@ mod tests {
     fn encrypt_and_decrypt_document() {
         let document_key: Bytes = "cac6c205eb06c8308d65156ff6c862c62b000b8ead121a4455a8ddeff7248128d895692136f240d5d1614dc7cc4147b1bd584bd617e30560bb872064d09ea325".from_hex().unwrap();
         let document: Bytes = b"Hello, world!"[..].into();
-        let encrypted_document = encrypt_document(document_key.clone(), document.clone()).unwrap();
+        let encrypted_document = encrypt_document(&document_key, document.clone()).unwrap();
         assert!(document != encrypted_document);
 
+        assert_eq!(encrypted_document.len(), document.len() + AEAD_OVERHEAD);
+        assert_eq!(encrypted_document[0], KDF_VERSION);
+
         let decrypted_document =
-            decrypt_document(document_key.clone(), encrypted_document).unwrap();
+            decrypt_document(&document_key, encrypted_document.clone()).unwrap();
         assert_eq!(document, decrypted_document);
+
+        let mut modified = encrypted_document.clone();
+        modified[1] ^= 1;
+        assert_eq!(
+            decrypt_document(&document_key, modified),
+            Err(errors::authentication())
+        );
+        let mut wrong_key = document_key.clone();
+        wrong_key[0] ^= 1;
+        assert_eq!(
+            decrypt_document(&wrong_key, encrypted_document),
+            Err(errors::authentication())
+        );
+    }
+
+    #[test]
+    fn binds_chunks_to_their_position() {
+        let document_key: Bytes = "cac6c205eb06c8308d65156ff6c862c62b000b8ead121a4455a8ddeff7248128d895692136f240d5d1614dc7cc4147b1bd584bd617e30560bb872064d09ea325".from_hex().unwrap();
+        let chunk = encrypt_chunk(&document_key, b"chunk".to_vec(), 1, false).unwrap();
+        assert_eq!(
+            decrypt_chunk(&document_key, chunk.clone(), 1, false).unwrap(),
+            b"chunk"
+        );
+        assert_eq!(
+            decrypt_chunk(&document_key, chunk.clone(), 0, false),
+            Err(errors::authentication())
+        );
+        assert_eq!(
+            decrypt_chunk(&document_key, chunk.clone(), 1, true),
+            Err(errors::authentication())
+        );
+        assert_eq!(
+            decrypt_document(&document_key, chunk),
+            Err(errors::authentication())
+        );
+    }
+
+    #[test]
+    fn derives_a_key_per_purpose() {
+        let document_key: Bytes = "cac6c205eb06c8308d65156ff6c862c62b000b8ead121a4455a8ddeff7248128d895692136f240d5d1614dc7cc4147b1bd584bd617e30560bb872064d09ea325".from_hex().unwrap();
+        let keys = DocumentKeys::derive(&document_key).unwrap();
+        assert_eq!(
+            keys.encryption.to_vec(),
+            "af4a3c9b9f20f02f0650749d64c0ca3e16d6d5810a62d0438416e9ac86399b5d"
+                .from_hex::<Vec<u8>>()
+                .unwrap()
+        );
+        assert_eq!(
+            keys.mac.to_vec(),
+            "b231c546f8172b636e8b78c62d67a23f58ee49d3389afafd6bedc565983db5df"
+                .from_hex::<Vec<u8>>()
+                .unwrap()
+        );
+        assert_eq!(
+            keys.metadata.to_vec(),
+            "11d0563f61d8ed9649c62f89feb007a1d00dc768fc6ba1a7f197379be892672d"
+                .from_hex::<Vec<u8>>()
+                .unwrap()
+        );
+        assert!(DocumentKeys::derive(&document_key[..32]).is_err());
+
+        let mut encrypted_document =
+            encrypt_document(&document_key, b"Hello, world!".to_vec()).unwrap();
+        encrypted_document[0] = KDF_VERSION + 1;
+        assert!(decrypt_document(&document_key, encrypted_document).is_err());
     }
 
     #[test]
-    fn encrypt_and_shadow_decrypt() {
+    fn hashes_personal_message() {
+        assert_eq!(
+            format!("{:x}", personal_message_hash(b"Hello World")),
+            "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+    }
+
+    #[test]
+    fn shadow_decrypt_legacy_document() {
         let document: Bytes = "deadbeef".from_hex().unwrap();
         let decrypted_secret = "843645726384530ffb0c52f175278143b5a93959af7864460f5a4fec9afd1450cfb8aef63dec90657f43f55b13e0a73c7524d4e9a13c051b4e5f1e53f39ecd91".parse().unwrap();
         let common_point = "07230e34ebfe41337d3ed53b186b3861751f2401ee74b988bba55694e2a6f60c757677e194be2e53c3523cc8548694e636e6acb35c4e8fdc5e29d28679b9b2f3".parse().unwrap();
@@ -155,13 +398,30 @@ mod tests {
         let encrypted_document = "2ddec1f96229efa2916988d8b2a82a47ef36f71c"
             .from_hex()
             .unwrap();
-        let decrypted_document = decrypt_document_with_shadow(
+        let key = decrypt_with_shadow_coefficients(decrypted_secret, common_point, shadows.clone())
+            .unwrap();
+        let decrypted_document = decrypt_legacy_document(&key, encrypted_document.clone()).unwrap();
+        assert_eq!(document, decrypted_document);
+
+        // a node answering garbage is an error, not a panic
+        assert_eq!(
+            decrypt_with_shadow_coefficients(decrypted_secret, common_point, vec![]),
+            Err(errors::invalid_document_key(
+                "decrypt_shadows",
+                "no shadows"
+            ))
+        );
+        assert!(
+            decrypt_with_shadow_coefficients(H512::zero(), common_point, shadows.clone()).is_err()
+        );
+
+        // the legacy vector is no aead ciphertext, legacy documents are never decrypted by guess
+        assert!(decrypt_document_with_shadow(
             decrypted_secret,
             common_point,
             shadows,
             encrypted_document,
         )
-        .unwrap();
-        assert_eq!(document, decrypted_document);
+        .is_err());
     }
 }
@@ -1,13 +1,23 @@
 pub mod args;
 pub mod bytes;
+pub mod cluster;
 pub mod cmd;
+pub mod config;
 pub mod dependency;
 pub mod document_key;
+pub mod eip712;
+pub mod envelope;
 pub mod errors;
 pub mod helpers;
 pub mod metadata;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod provenance;
+pub mod records;
 pub mod secretstore;
+pub mod storage;
+pub mod stream;
+pub mod threshold;
 pub mod util;
 // #[macro_use]
 extern crate structopt;
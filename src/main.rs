@@ -1,21 +1,78 @@
 extern crate secret_store_cli;
 
-use secret_store_cli::{args, args::*, cmd};
+use std::process;
+
+use secret_store_cli::{args, args::*, cmd, config};
 
 fn main() {
-    let args = args::parse();
-    match args {
+    let opts = args::parse();
+    let profile = config::load(opts.profile.as_ref().map(String::as_str)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    match opts.cmd {
         Args::Decrypt {
             dockey_id,
-            ipfs_uri,
+            location,
+            output,
+        } => {
+            cmd::decrypt(&profile, dockey_id, location, output);
+        }
+        Args::Encrypt {
+            file,
+            threshold,
+            server_generated,
+            keep_ciphertext,
+            chunk_size,
+            recipients,
+        } => {
+            let options = cmd::EncryptOptions {
+                threshold,
+                server_generated,
+                keep_ciphertext,
+                chunk_size,
+                recipients,
+            };
+            cmd::encrypt(&profile, file, options);
+        }
+        Args::KeyInfo { dockey_id } => {
+            cmd::key_info(&profile, dockey_id);
+        }
+        Args::Sign {
+            key_id,
+            message_hash,
+            scheme,
         } => {
-            cmd::decrypt(dockey_id, ipfs_uri);
+            cmd::sign(&profile, key_id, message_hash, scheme);
         }
-        Args::Encrypt { file } => {
-            cmd::encrypt(file);
+        Args::SignMessage { message } => {
+            cmd::sign_message(&profile, message);
+        }
+        Args::SignTypedData { file } => {
+            cmd::sign_typed_data(&profile, file);
+        }
+        Args::Pin { cmd: pin } => match pin {
+            PinCmd::Add { cid } => cmd::pin_add(&profile, cid),
+            PinCmd::Rm { cid, force } => cmd::pin_rm(&profile, cid, force),
+            PinCmd::Ls => cmd::pin_ls(&profile),
+        },
+        Args::Admin {
+            cmd: AdminCmd::ServersSetChange { old, new },
+        } => {
+            cmd::servers_set_change(&profile, old, new);
+        }
+        Args::Recover { signature, signed } => {
+            cmd::recover(signature, signed);
+        }
+        Args::Verify {
+            signature,
+            address,
+            signed,
+        } => {
+            cmd::verify(signature, signed, address);
         }
         Args::Address => {
-            cmd::address();
+            cmd::address(&profile);
         }
     }
 }
@@ -0,0 +1,367 @@
+//! In-process stand-in for a Secret Store cluster HTTP API.
+//!
+//! Keys are generated with `threshold::generate_joint_key` and split between `nodes` key
+//! shares, retrievals combine `threshold + 1` of those shares the way the cluster does. Only
+//! the endpoints used by `encrypt` and `decrypt` are served, there is no permissioning
+//! contract, every valid signature is accepted.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use ethereum_types::H256;
+use ethkey::{self, Public, Signature};
+use hyper::rt::{self, Future};
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use parity_crypto::DEFAULT_MAC;
+
+use crate::bytes::Bytes;
+use crate::helpers::generate_document_key;
+use crate::threshold::{
+    decrypt_document_key, decrypt_shadows, generate_joint_key, JointKey, KeyShare,
+};
+
+type HandlerResult = Result<String, (StatusCode, &'static str)>;
+
+const BAD_SIGNATURE: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "Bad signature");
+const BAD_REQUEST: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "Bad request");
+const ACCESS_DENIED: (StatusCode, &str) = (StatusCode::FORBIDDEN, "Access dened");
+const SERVER_KEY_NOT_FOUND: (StatusCode, &str) = (
+    StatusCode::NOT_FOUND,
+    "Server key with this ID is not found",
+);
+const DOCUMENT_KEY_NOT_FOUND: (StatusCode, &str) = (
+    StatusCode::NOT_FOUND,
+    "Document key with this ID is not found",
+);
+const SERVER_KEY_ALREADY_GENERATED: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "Server key with this ID is already generated",
+);
+const DOCUMENT_KEY_ALREADY_STORED: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "Document key with this ID is already stored",
+);
+const NOT_ENOUGH_NODES: (StatusCode, &str) = (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "not enough nodes for passed threshold",
+);
+const INTERNAL_ERROR: (StatusCode, &str) = (StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+
+struct ServerKey {
+    author: Public,
+    joint_key: JointKey,
+    /// `(common point, encrypted point)` of the stored document key.
+    document_key: Option<(Public, Public)>,
+}
+
+impl ServerKey {
+    /// Shares of the first `threshold + 1` nodes, enough to serve any session.
+    fn quorum(&self) -> &[KeyShare] {
+        &self.joint_key.shares[..=self.joint_key.threshold]
+    }
+}
+
+pub struct MockSecretStore {
+    nodes: usize,
+    keys: Mutex<HashMap<H256, ServerKey>>,
+}
+
+impl MockSecretStore {
+    pub fn new(nodes: usize) -> Self {
+        MockSecretStore {
+            nodes,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn handle(&self, method: &Method, path: &str) -> Response<Body> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let result = match (method, segments.as_slice()) {
+            (&Method::POST, ["shadow", key_id, signature, threshold]) => {
+                self.generate_server_key(key_id, signature, threshold)
+            }
+            (&Method::POST, ["shadow", key_id, signature, common_point, encrypted_point]) => {
+                self.store_document_key(key_id, signature, common_point, encrypted_point)
+            }
+            (&Method::GET, ["shadow", key_id, signature]) => {
+                self.retrieve_document_key_shadow(key_id, signature)
+            }
+            (&Method::GET, ["server", key_id, signature]) => {
+                self.retrieve_server_key(key_id, signature)
+            }
+            (&Method::POST, [key_id, signature, threshold]) => {
+                self.generate_document_key(key_id, signature, threshold)
+            }
+            (&Method::GET, [key_id, signature]) => self.retrieve_document_key(key_id, signature),
+            _ => Err(BAD_REQUEST),
+        };
+        let (status, body) = match result {
+            Ok(body) => (StatusCode::OK, body),
+            Err((status, message)) => (status, json(&message)),
+        };
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        response
+    }
+
+    fn generate_server_key(&self, key_id: &str, signature: &str, threshold: &str) -> HandlerResult {
+        let (key_id, requester) = requester(key_id, signature)?;
+        let threshold: usize = threshold.parse().map_err(|_| BAD_REQUEST)?;
+        let public = self.generate(key_id, requester, threshold)?;
+        Ok(json(&public))
+    }
+
+    fn generate_document_key(
+        &self,
+        key_id: &str,
+        signature: &str,
+        threshold: &str,
+    ) -> HandlerResult {
+        let (key_id, requester) = requester(key_id, signature)?;
+        let threshold: usize = threshold.parse().map_err(|_| BAD_REQUEST)?;
+        let public = self.generate(key_id, requester, threshold)?;
+        let document_key = generate_document_key(requester, public).map_err(|_| INTERNAL_ERROR)?;
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys.get_mut(&key_id).ok_or(SERVER_KEY_NOT_FOUND)?;
+        key.document_key = Some((
+            document_key.common_point.into(),
+            document_key.encrypted_point.into(),
+        ));
+        Ok(json(&document_key.encrypted_key))
+    }
+
+    fn store_document_key(
+        &self,
+        key_id: &str,
+        signature: &str,
+        common_point: &str,
+        encrypted_point: &str,
+    ) -> HandlerResult {
+        let (key_id, requester) = requester(key_id, signature)?;
+        let common_point: Public = common_point.parse().map_err(|_| BAD_REQUEST)?;
+        let encrypted_point: Public = encrypted_point.parse().map_err(|_| BAD_REQUEST)?;
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys.get_mut(&key_id).ok_or(SERVER_KEY_NOT_FOUND)?;
+        if key.author != requester {
+            return Err(ACCESS_DENIED);
+        }
+        if key.document_key.is_some() {
+            return Err(DOCUMENT_KEY_ALREADY_STORED);
+        }
+        key.document_key = Some((common_point, encrypted_point));
+        Ok(String::new())
+    }
+
+    fn retrieve_server_key(&self, key_id: &str, signature: &str) -> HandlerResult {
+        let (key_id, _) = requester(key_id, signature)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(&key_id).ok_or(SERVER_KEY_NOT_FOUND)?;
+        Ok(json(&key.joint_key.public))
+    }
+
+    fn retrieve_document_key(&self, key_id: &str, signature: &str) -> HandlerResult {
+        let (key_id, requester) = requester(key_id, signature)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(&key_id).ok_or(SERVER_KEY_NOT_FOUND)?;
+        let (common_point, encrypted_point) = key.document_key.ok_or(DOCUMENT_KEY_NOT_FOUND)?;
+        let document_key = decrypt_document_key(&common_point, &encrypted_point, key.quorum())
+            .map_err(|_| INTERNAL_ERROR)?;
+        let encrypted_key =
+            ethkey::crypto::ecies::encrypt(&requester, &DEFAULT_MAC, document_key.as_bytes())
+                .map_err(|_| INTERNAL_ERROR)?;
+        Ok(json(&Bytes::new(encrypted_key)))
+    }
+
+    fn retrieve_document_key_shadow(&self, key_id: &str, signature: &str) -> HandlerResult {
+        let (key_id, requester) = requester(key_id, signature)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(&key_id).ok_or(SERVER_KEY_NOT_FOUND)?;
+        let (common_point, encrypted_point) = key.document_key.ok_or(DOCUMENT_KEY_NOT_FOUND)?;
+        let decrypted = decrypt_shadows(&requester, &common_point, &encrypted_point, key.quorum())
+            .map_err(|_| INTERNAL_ERROR)?;
+        Ok(json(&decrypted))
+    }
+
+    fn generate(
+        &self,
+        key_id: H256,
+        author: Public,
+        threshold: usize,
+    ) -> Result<Public, (StatusCode, &'static str)> {
+        if threshold >= self.nodes {
+            return Err(NOT_ENOUGH_NODES);
+        }
+        let mut keys = self.keys.lock().unwrap();
+        if keys.contains_key(&key_id) {
+            return Err(SERVER_KEY_ALREADY_GENERATED);
+        }
+        let joint_key = generate_joint_key(threshold, self.nodes).map_err(|_| INTERNAL_ERROR)?;
+        let public = joint_key.public;
+        keys.insert(
+            key_id,
+            ServerKey {
+                author,
+                joint_key,
+                document_key: None,
+            },
+        );
+        Ok(public)
+    }
+}
+
+/// Starts serving on `addr`, returning the bound address and the server future.
+pub fn serve(
+    addr: &SocketAddr,
+    nodes: usize,
+) -> Result<(SocketAddr, impl Future<Item = (), Error = ()>), hyper::Error> {
+    let store = Arc::new(MockSecretStore::new(nodes));
+    let server = Server::try_bind(addr)?.serve(move || {
+        let store = store.clone();
+        service_fn_ok(move |req: Request<Body>| store.handle(req.method(), req.uri().path()))
+    });
+    let local_addr = server.local_addr();
+    Ok((
+        local_addr,
+        server.map_err(|e| eprintln!("mock secret store error: {}", e)),
+    ))
+}
+
+/// Runs a mock cluster on a background thread, returning its url.
+pub fn spawn(nodes: usize) -> String {
+    let (addr, server) = serve(&([127, 0, 0, 1], 0).into(), nodes).expect("bind to localhost");
+    thread::spawn(move || rt::run(server));
+    format!("http://{}", addr)
+}
+
+fn requester(key_id: &str, signature: &str) -> Result<(H256, Public), (StatusCode, &'static str)> {
+    let key_id: H256 = key_id.parse().map_err(|_| BAD_REQUEST)?;
+    let signature: Signature = signature.parse().map_err(|_| BAD_SIGNATURE)?;
+    let public = ethkey::recover(&signature, &key_id).map_err(|_| BAD_SIGNATURE)?;
+    Ok((key_id, public))
+}
+
+fn json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("serializable; qed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{Error, SecretStoreHttpClient};
+    use crate::helpers::{decrypt_document_with_shadow, encrypt_document};
+    use ethkey::{Generator, KeyPair, Random, Secret};
+
+    fn signed_key_id(key_pair: &KeyPair, key_id: &H256) -> Signature {
+        ethkey::sign(key_pair.secret(), key_id).unwrap()
+    }
+
+    #[test]
+    fn store_and_shadow_retrieve_document_key() {
+        let client = SecretStoreHttpClient::new(&spawn(3), None).unwrap();
+        let requester = Random.generate().unwrap();
+        let key_id = H256::random();
+        let signature = signed_key_id(&requester, &key_id);
+
+        let server_key = client.generate_server_key(&key_id, &signature, 1).unwrap();
+        let document_key = generate_document_key(*requester.public(), server_key.into()).unwrap();
+        let key = ethkey::crypto::ecies::decrypt(
+            requester.secret(),
+            &DEFAULT_MAC,
+            &document_key.encrypted_key.0,
+        )
+        .unwrap();
+        let document = b"Hello, world!".to_vec();
+        let encrypted_document = encrypt_document(&key, document.clone()).unwrap();
+        client
+            .store_document_key(
+                &key_id,
+                &signature,
+                &document_key.common_point,
+                &document_key.encrypted_point,
+            )
+            .unwrap();
+
+        let decrypted = client
+            .retrieve_document_key_shadow(&key_id, &signature)
+            .unwrap();
+        assert_eq!(decrypted.decrypt_shadows.len(), 2);
+        let shadows = decrypted
+            .decrypt_shadows
+            .iter()
+            .map(|shadow| {
+                let shadow =
+                    ethkey::crypto::ecies::decrypt(requester.secret(), &DEFAULT_MAC, &shadow.0)
+                        .unwrap();
+                Secret::from_unsafe_slice(&shadow).unwrap()
+            })
+            .collect();
+        let decrypted_document = decrypt_document_with_shadow(
+            decrypted.decrypted_secret.into(),
+            decrypted.common_point.into(),
+            shadows,
+            encrypted_document,
+        )
+        .unwrap();
+        assert_eq!(decrypted_document, document);
+    }
+
+    #[test]
+    fn server_generated_document_key() {
+        let client = SecretStoreHttpClient::new(&spawn(2), None).unwrap();
+        let requester = Random.generate().unwrap();
+        let key_id = H256::random();
+        let signature = signed_key_id(&requester, &key_id);
+
+        let encrypted_key = client
+            .generate_document_key(&key_id, &signature, 1)
+            .unwrap();
+        let retrieved_key = client.retrieve_document_key(&key_id, &signature).unwrap();
+        let decrypt = |key: &Bytes| {
+            ethkey::crypto::ecies::decrypt(requester.secret(), &DEFAULT_MAC, &key.0).unwrap()
+        };
+        assert_eq!(decrypt(&encrypted_key), decrypt(&retrieved_key));
+        assert_eq!(
+            client.retrieve_server_key(&key_id, &signature).unwrap(),
+            client
+                .retrieve_server_key(
+                    &key_id,
+                    &signed_key_id(&Random.generate().unwrap(), &key_id)
+                )
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_cluster_errors() {
+        let client = SecretStoreHttpClient::new(&spawn(2), None).unwrap();
+        let requester = Random.generate().unwrap();
+        let key_id = H256::random();
+        let signature = signed_key_id(&requester, &key_id);
+
+        match client.retrieve_document_key_shadow(&key_id, &signature) {
+            Err(Error::ServerKeyNotFound(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match client.generate_server_key(&key_id, &signature, 2) {
+            Err(Error::NotEnoughNodes(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let server_key = client.generate_server_key(&key_id, &signature, 1).unwrap();
+        match client.generate_server_key(&key_id, &signature, 1) {
+            Err(Error::ServerKeyAlreadyGenerated(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match client.retrieve_document_key_shadow(&key_id, &signature) {
+            Err(Error::DocumentKeyNotFound(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let other = signed_key_id(&Random.generate().unwrap(), &key_id);
+        match client.store_document_key(&key_id, &other, &server_key, &server_key) {
+            Err(Error::AccessDenied(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Local list of the documents encrypted by this cli.
+
+use std::{
+    fs,
+    io::{self, Result},
+    path::{Path, PathBuf},
+};
+
+use ethereum_types::H256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    pub dockey_id: H256,
+    /// Where the ciphertext was stored, an IPFS hash with the default storage.
+    pub location: String,
+    /// Chunk locations of a chunked document, `location` holds its manifest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+    /// Server key threshold, `threshold + 1` nodes are needed to retrieve the document key.
+    pub threshold: usize,
+    /// Name of the encrypted file, restored by `decrypt --output <dir>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecordsFile {
+    documents: Vec<DocumentRecord>,
+}
+
+pub struct Records {
+    path: PathBuf,
+    documents: Vec<DocumentRecord>,
+}
+
+impl Records {
+    /// Opens the records file, a missing file yields an empty list.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => RecordsFile::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Records {
+            path: path.into(),
+            documents: file.documents,
+        })
+    }
+
+    pub fn get(&self, dockey_id: &H256) -> Option<&DocumentRecord> {
+        self.documents.iter().find(|r| r.dockey_id == *dockey_id)
+    }
+
+    pub fn documents(&self) -> &[DocumentRecord] {
+        &self.documents
+    }
+
+    /// Adds a record, replacing any previous one with the same dockey id.
+    pub fn insert(&mut self, record: DocumentRecord) {
+        self.documents.retain(|r| r.dockey_id != record.dockey_id);
+        self.documents.push(record);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = RecordsFile {
+            documents: self.documents.clone(),
+        };
+        let contents = serde_json::to_vec_pretty(&file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&self.path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_save_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records.json");
+        let mut records = Records::open(&path).unwrap();
+        assert!(records.documents().is_empty());
+
+        let dockey_id = H256::from_low_u64_be(1);
+        records.insert(DocumentRecord {
+            dockey_id,
+            location: "QmA".into(),
+            chunks: Vec::new(),
+            threshold: 1,
+            file_name: None,
+        });
+        records.insert(DocumentRecord {
+            dockey_id,
+            location: "QmB".into(),
+            chunks: vec!["QmC".into()],
+            threshold: 2,
+            file_name: Some("main.js".into()),
+        });
+        records.save().unwrap();
+
+        let records = Records::open(&path).unwrap();
+        assert_eq!(records.documents().len(), 1);
+        assert_eq!(records.get(&dockey_id).unwrap().location, "QmB");
+        assert_eq!(records.get(&dockey_id).unwrap().threshold, 2);
+        assert_eq!(records.get(&dockey_id).unwrap().chunks, vec!["QmC"]);
+        assert_eq!(
+            records.get(&dockey_id).unwrap().file_name,
+            Some("main.js".into())
+        );
+    }
+}
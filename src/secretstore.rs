@@ -1,9 +1,11 @@
 use crate::bytes::Bytes;
-use crate::document_key::EncryptedDocumentKey;
+use crate::document_key::{EncryptedDocumentKey, SecretBytes};
+use crate::eip712::TypedData;
+use crate::envelope::Cipher;
 use crate::errors;
 use crate::helpers::{
-    decrypt_document, decrypt_document_with_shadow, encrypt_document, generate_document_key,
-    ordered_servers_keccak,
+    decrypt_with_shadow_coefficients, encrypt_document, generate_document_key,
+    ordered_servers_keccak, personal_message_hash,
 };
 use ethcore_accounts::AccountProvider;
 use ethereum_types::{H160, H256, H512};
@@ -26,9 +28,10 @@ impl SecretStoreClient {
         }
     }
     // decrypt_key
-    fn decrypt_key(&self, address: H160, password: Password, key: Bytes) -> Result<Vec<u8>> {
+    fn decrypt_key(&self, address: H160, password: Password, key: Bytes) -> Result<SecretBytes> {
         self.accounts
             .decrypt(address.into(), Some(password), &DEFAULT_MAC, &key.0)
+            .map(SecretBytes::from)
             .map_err(|e| errors::account("Could not decrypt key.", e))
     }
 
@@ -38,6 +41,17 @@ impl SecretStoreClient {
             Secret::from_unsafe_slice(&s).map_err(|e| errors::account("invalid secret", e))
         })
     }
+
+    // sign_message_hash, `v` is 27/28 as wallets return it for messages
+    fn sign_message_hash(&self, address: H160, password: Password, hash: H256) -> Result<Bytes> {
+        let signature = self
+            .accounts
+            .sign(address.into(), Some(password), hash.into())
+            .map_err(|e| errors::account("Could not sign message.", e))?;
+        let mut signature = (*signature).to_vec();
+        signature[64] += 27;
+        Ok(Bytes::new(signature))
+    }
 }
 
 #[rpc]
@@ -53,9 +67,21 @@ pub trait SecretStore {
     #[rpc(name = "secretstore_encrypt")]
     fn encrypt(&self, address: H160, password: Password, key: Bytes, data: Bytes) -> Result<Bytes>;
 
+    /// `cipher` is `aes-256-gcm` unless given, what `secretstore_encrypt` writes. Legacy
+    /// documents need `aes-128-ctr`.
     #[rpc(name = "secretstore_decrypt")]
-    fn decrypt(&self, address: H160, password: Password, key: Bytes, data: Bytes) -> Result<Bytes>;
+    fn decrypt(
+        &self,
+        address: H160,
+        password: Password,
+        key: Bytes,
+        data: Bytes,
+        cipher: Option<Cipher>,
+    ) -> Result<Bytes>;
 
+    /// `cipher` is `aes-128-ctr` unless given, as the method always decrypted, documents of
+    /// `secretstore_encrypt` need `aes-256-gcm`.
+    #[allow(clippy::too_many_arguments)]
     #[rpc(name = "secretstore_shadowDecrypt")]
     fn shadow_decrypt(
         &self,
@@ -65,6 +91,7 @@ pub trait SecretStore {
         common_point: H512,
         decrypt_shadows: Vec<Bytes>,
         data: Bytes,
+        cipher: Option<Cipher>,
     ) -> Result<Bytes>;
 
     #[rpc(name = "secretstore_serversSetHash")]
@@ -72,6 +99,24 @@ pub trait SecretStore {
 
     #[rpc(name = "secretstore_signRawHash")]
     fn sign_raw_hash(&self, address: H160, password: Password, raw_hash: H256) -> Result<Bytes>;
+
+    /// EIP-191 `personal_sign` of the message.
+    #[rpc(name = "secretstore_signPersonalMessage")]
+    fn sign_personal_message(
+        &self,
+        address: H160,
+        password: Password,
+        message: Bytes,
+    ) -> Result<Bytes>;
+
+    /// EIP-712 signature of typed data in the `eth_signTypedData` JSON form.
+    #[rpc(name = "secretstore_signTypedData")]
+    fn sign_typed_data(
+        &self,
+        address: H160,
+        password: Password,
+        typed_data: TypedData,
+    ) -> Result<Bytes>;
 }
 
 impl SecretStore for SecretStoreClient {
@@ -89,11 +134,22 @@ impl SecretStore for SecretStoreClient {
     }
 
     fn encrypt(&self, address: H160, password: Password, key: Bytes, data: Bytes) -> Result<Bytes> {
-        encrypt_document(self.decrypt_key(address, password, key)?, data.0).map(Into::into)
+        encrypt_document(&self.decrypt_key(address, password, key)?, data.0).map(Into::into)
     }
 
-    fn decrypt(&self, address: H160, password: Password, key: Bytes, data: Bytes) -> Result<Bytes> {
-        decrypt_document(self.decrypt_key(address, password, key)?, data.0).map(Into::into)
+    fn decrypt(
+        &self,
+        address: H160,
+        password: Password,
+        key: Bytes,
+        data: Bytes,
+        cipher: Option<Cipher>,
+    ) -> Result<Bytes> {
+        let key = self.decrypt_key(address, password, key)?;
+        cipher
+            .unwrap_or(Cipher::Aes256Gcm)
+            .decrypt(&key, data.0)
+            .map(Into::into)
     }
 
     fn shadow_decrypt(
@@ -104,13 +160,18 @@ impl SecretStore for SecretStoreClient {
         common_point: H512,
         decrypt_shadows: Vec<Bytes>,
         data: Bytes,
+        cipher: Option<Cipher>,
     ) -> Result<Bytes> {
         let mut shadows = Vec::with_capacity(decrypt_shadows.len());
         for decrypt_shadow in decrypt_shadows {
             shadows.push(self.decrypt_secret(address.clone(), password.clone(), decrypt_shadow)?);
         }
 
-        decrypt_document_with_shadow(decrypt_secret.into(), common_point.into(), shadows, data.0)
+        let key =
+            decrypt_with_shadow_coefficients(decrypt_secret.into(), common_point.into(), shadows)?;
+        cipher
+            .unwrap_or(Cipher::Aes128Ctr)
+            .decrypt(&key, data.0)
             .map(Into::into)
     }
 
@@ -124,13 +185,34 @@ impl SecretStore for SecretStoreClient {
             .map(|s| Bytes::new((*s).to_vec()))
             .map_err(|e| errors::account("Could not sign raw hash.", e))
     }
+
+    fn sign_personal_message(
+        &self,
+        address: H160,
+        password: Password,
+        message: Bytes,
+    ) -> Result<Bytes> {
+        self.sign_message_hash(address, password, personal_message_hash(&message.0))
+    }
+
+    fn sign_typed_data(
+        &self,
+        address: H160,
+        password: Password,
+        typed_data: TypedData,
+    ) -> Result<Bytes> {
+        let hash = typed_data
+            .hash()
+            .map_err(|e| errors::invalid_params("typed_data", e))?;
+        self.sign_message_hash(address, password, hash)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use ethcore_accounts::AccountProvider;
-    use ethkey::{verify_public, KeyPair, Signature};
+    use ethkey::{public_to_address, recover, verify_public, KeyPair, Signature};
     use parity_crypto::DEFAULT_MAC;
 
     #[test]
@@ -163,6 +245,57 @@ mod tests {
         assert!(verify_public(&keypair.public(), &signature, &message).unwrap());
     }
 
+    #[test]
+    fn sign_messages_is_ok() {
+        // keccak256("cow")
+        let secret = "c85ef7d79691fe79573b1a7064c19c1a9819ebdbd1faaab1a8ec92344438aaf4"
+            .parse()
+            .unwrap();
+        let ap = AccountProvider::transient_provider();
+        let account = ap.insert_account(secret, &"test".into()).unwrap();
+        assert_eq!(
+            account,
+            "cd2a3d9f938e13cd947ec05abc7fe734df8dd826".parse().unwrap()
+        );
+        let secretstore_new = SecretStoreClient::new(&Arc::new(ap));
+        let signer = |signature: Bytes, hash: H256| {
+            let mut raw = [0u8; 65];
+            raw.copy_from_slice(&signature.0);
+            assert!(raw[64] == 27 || raw[64] == 28);
+            raw[64] -= 27;
+            public_to_address(&recover(&Signature::from(raw), &hash).unwrap())
+        };
+
+        let message = b"Hello World".to_vec();
+        let signature = secretstore_new
+            .sign_personal_message(account, "test".into(), Bytes::new(message.clone()))
+            .unwrap();
+        assert_eq!(signer(signature, personal_message_hash(&message)), account);
+
+        let typed_data: TypedData = serde_json::from_str(
+            r#"{
+            "types": {
+                "EIP712Domain": [{ "name": "name", "type": "string" }],
+                "Grant": [{ "name": "dockey", "type": "bytes32" }]
+            },
+            "primaryType": "Grant",
+            "domain": { "name": "secret-store-cli" },
+            "message": { "dockey": "0x0000000000000000000000000000000000000000000000000000000000000001" }
+        }"#,
+        )
+        .unwrap();
+        let signature = secretstore_new
+            .sign_typed_data(account, "test".into(), typed_data.clone())
+            .unwrap();
+        assert_eq!(signer(signature, typed_data.hash().unwrap()), account);
+
+        let mut invalid = typed_data;
+        invalid.message["dockey"] = "0x01".into();
+        assert!(secretstore_new
+            .sign_typed_data(account, "test".into(), invalid)
+            .is_err());
+    }
+
     #[test]
     fn generate_document_key_is_ok() {
         let secret = "82758356bf46b42710d3946a8efa612b7bf5e125e4d49f28facf1139db4a46f4"
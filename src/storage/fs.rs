@@ -0,0 +1,67 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{check_content_name, content_name, Storage};
+
+/// Keeps every ciphertext in `root`, named by its sha256.
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: &Path) -> Self {
+        FsStorage { root: root.into() }
+    }
+
+    fn path(&self, location: &str) -> io::Result<PathBuf> {
+        check_content_name(location)?;
+        Ok(self.root.join(location))
+    }
+}
+
+impl Storage for FsStorage {
+    fn put(&self, data: &[u8]) -> io::Result<String> {
+        let name = content_name(data);
+        fs::create_dir_all(&self.root)?;
+        fs::write(self.root.join(&name), data)?;
+        Ok(name)
+    }
+
+    fn get(&self, location: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(location)?)
+    }
+
+    fn exists(&self, location: &str) -> io::Result<bool> {
+        Ok(self.path(location)?.is_file())
+    }
+
+    fn delete(&self, location: &str) -> io::Result<()> {
+        fs::remove_file(self.path(location)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_delete() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FsStorage::new(root.path());
+
+        let location = storage.put(b"ciphertext").unwrap();
+        assert_eq!(storage.put(b"ciphertext").unwrap(), location);
+        assert!(storage.exists(&location).unwrap());
+        assert_eq!(storage.get(&location).unwrap(), b"ciphertext");
+
+        storage.delete(&location).unwrap();
+        assert!(!storage.exists(&location).unwrap());
+        assert_eq!(
+            storage.get(&location).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(storage.get("../../etc/passwd").is_err());
+    }
+}
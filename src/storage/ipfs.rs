@@ -0,0 +1,291 @@
+use std::{
+    fmt,
+    io::{self, Cursor},
+    str::FromStr,
+};
+
+use futures::{Future, Stream};
+use ipfs_api::{response, IpfsClient};
+use reqwest::{
+    multipart::{Form, Part},
+    Client,
+};
+use tokio::runtime::current_thread::Runtime;
+use url::Url;
+
+use super::{other_error, Storage};
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+/// Multicodec of the unixfs nodes created by `ipfs add`.
+const DAG_PB: u8 = 0x70;
+
+/// Content identifier of an IPFS object, base58 for version 0 and base32 for version 1.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cid(String);
+
+impl Cid {
+    pub fn version(&self) -> u32 {
+        if self.0.starts_with("Qm") {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Same object addressed by a version 1 CID.
+    pub fn to_v1(&self) -> Cid {
+        if self.version() == 1 {
+            return self.clone();
+        }
+        let mut bytes = vec![1, DAG_PB];
+        bytes.extend(base58_decode(&self.0).expect("checked when parsed; qed"));
+        Cid(format!("b{}", base32_encode(&bytes)))
+    }
+}
+
+impl FromStr for Cid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches("/ipfs/");
+        let valid = if s.starts_with("Qm") {
+            // sha2-256 multihash
+            base58_decode(s).map_or(false, |hash| hash.len() == 34 && hash[..2] == [0x12, 0x20])
+        } else if s.starts_with('b') {
+            // version 1, a single byte codec and a sha2-256 multihash
+            base32_decode(&s[1..]).map_or(false, |cid| {
+                cid.len() == 36 && cid[0] == 1 && cid[1] < 0x80 && cid[2..4] == [0x12, 0x20]
+            })
+        } else {
+            false
+        };
+        if valid {
+            Ok(Cid(s.into()))
+        } else {
+            Err(format!("invalid CID: {}", s))
+        }
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    // little endian while decoding
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    bytes.extend(s.bytes().take_while(|&c| c == b'1').map(|_| 0));
+    bytes.reverse();
+    Some(bytes)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in data {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        buffer = (buffer << 5) | BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// IPFS daemon reached through its HTTP API.
+pub struct IpfsStorage {
+    api: Url,
+    client: IpfsClient,
+    pin: bool,
+    cid_version: u32,
+}
+
+impl IpfsStorage {
+    /// `url` is the API address of the daemon, e.g. `http://127.0.0.1:5001`.
+    pub fn new(url: &str, pin: bool, cid_version: u32) -> io::Result<Self> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid ipfs url {}: {}", url, reason),
+            )
+        };
+        let parsed = Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        if parsed.scheme() != "http" {
+            return Err(invalid("only http api addresses are supported"));
+        }
+        if cid_version > 1 {
+            return Err(invalid(&format!("unknown cid version {}", cid_version)));
+        }
+        let host = parsed.host_str().ok_or_else(|| invalid("missing host"))?;
+        let client = IpfsClient::new(host, parsed.port().unwrap_or(5001))
+            .map_err(|e| invalid(&e.to_string()))?;
+        Ok(IpfsStorage {
+            api: parsed,
+            client,
+            pin,
+            cid_version,
+        })
+    }
+
+    /// Adds `data`, pinning it unless pin-on-add is disabled.
+    pub fn add(&self, data: &[u8]) -> io::Result<Cid> {
+        let hash = if self.pin {
+            run("add", self.client.add(Cursor::new(data.to_vec())))?.hash
+        } else {
+            self.add_unpinned(data)?
+        };
+        let cid: Cid = hash.parse().map_err(other_error)?;
+        Ok(match self.cid_version {
+            0 => cid,
+            _ => cid.to_v1(),
+        })
+    }
+
+    /// `ipfs-api` always pins on add. Unpinning afterwards would also drop a pin the content
+    /// already had, so the HTTP API is called directly with `pin=false`.
+    fn add_unpinned(&self, data: &[u8]) -> io::Result<String> {
+        let failed = |e: &dyn fmt::Display| other_error(format!("ipfs add failed: {}", e));
+        let mut url = self.api.join("api/v0/add").map_err(|e| failed(&e))?;
+        url.query_pairs_mut().append_pair("pin", "false");
+        let form = Form::new().part("file", Part::bytes(data.to_vec()).file_name("document"));
+        let mut response = Client::new()
+            .post(url.as_str())
+            .multipart(form)
+            .send()
+            .map_err(|e| failed(&e))?;
+        let body = response.text().map_err(|e| failed(&e))?;
+        if !response.status().is_success() {
+            return Err(failed(&body.trim()));
+        }
+        serde_json::from_str::<response::AddResponse>(&body)
+            .map(|added| added.hash)
+            .map_err(|e| failed(&e))
+    }
+
+    pub fn pin_add(&self, cid: &Cid) -> io::Result<()> {
+        run("pin add", self.client.pin_add(&cid.0, true)).map(|_| ())
+    }
+
+    pub fn pin_rm(&self, cid: &Cid) -> io::Result<()> {
+        run("pin rm", self.client.pin_rm(&cid.0, true)).map(|_| ())
+    }
+
+    /// Recursively pinned objects, these are what `add` keeps. Keys are as listed by the
+    /// daemon, which may use CIDs `Cid` does not read.
+    pub fn pins(&self) -> io::Result<Vec<String>> {
+        let pins = run("pin ls", self.client.pin_ls(None, Some("recursive")))?;
+        Ok(pins.keys.into_iter().map(|(key, _)| key).collect())
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> io::Result<bool> {
+        match run_raw(self.client.pin_ls(Some(&cid.0), Some("recursive")))? {
+            Ok(_) => Ok(true),
+            // the daemon answers "not pinned" as an api error
+            Err(response::Error::Api(_)) => Ok(false),
+            Err(e) => Err(other_error(format!("ipfs pin ls failed: {}", e))),
+        }
+    }
+}
+
+impl Storage for IpfsStorage {
+    fn put(&self, data: &[u8]) -> io::Result<String> {
+        self.add(data).map(|cid| cid.to_string())
+    }
+
+    fn get(&self, location: &str) -> io::Result<Vec<u8>> {
+        let cid: Cid = location.parse().map_err(other_error)?;
+        let data = run("cat", self.client.cat(&cid.0).concat2())?;
+        Ok(data.to_vec())
+    }
+
+    /// Content we stored is pinned, anything else may be garbage collected at any time.
+    fn exists(&self, location: &str) -> io::Result<bool> {
+        self.is_pinned(&location.parse().map_err(other_error)?)
+    }
+
+    fn delete(&self, location: &str) -> io::Result<()> {
+        self.pin_rm(&location.parse().map_err(other_error)?)
+    }
+}
+
+/// Drives an api call to completion on the current thread.
+fn run_raw<F: Future>(future: F) -> io::Result<Result<F::Item, F::Error>> {
+    Ok(Runtime::new()?.block_on(future))
+}
+
+fn run<F>(command: &str, future: F) -> io::Result<F::Item>
+where
+    F: Future<Error = response::Error>,
+{
+    run_raw(future)?.map_err(|e| other_error(format!("ipfs {} failed: {}", command, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_cid_to_v1() {
+        let cid: Cid = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"
+            .parse()
+            .unwrap();
+        assert_eq!(cid.version(), 0);
+        let v1 = cid.to_v1();
+        assert_eq!(v1.version(), 1);
+        assert_eq!(
+            v1.to_string(),
+            "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+        );
+        assert_eq!(v1.to_string().parse::<Cid>().unwrap(), v1);
+    }
+
+    #[test]
+    fn rejects_invalid_cids() {
+        assert!("QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMn0"
+            .parse::<Cid>()
+            .is_err());
+        assert!("QmbWqx".parse::<Cid>().is_err());
+        assert!("bafy!".parse::<Cid>().is_err());
+        assert!("ba".parse::<Cid>().is_err());
+        // one character short of a sha2-256 multihash
+        assert!("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzd"
+            .parse::<Cid>()
+            .is_err());
+        assert!("../etc/passwd".parse::<Cid>().is_err());
+        assert!(IpfsStorage::new("https://ipfs.infura.io:5001", true, 0).is_err());
+        assert!(IpfsStorage::new("http://127.0.0.1:5001", true, 2).is_err());
+    }
+}
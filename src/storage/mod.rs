@@ -0,0 +1,145 @@
+//! Where encrypted documents are kept.
+//!
+//! The backend is picked by the `storage` table of a profile:
+//!
+//! ```toml
+//! [profiles.dev.storage]
+//! backend = "fs"
+//! path = "ciphertexts"
+//! ```
+//!
+//! Without one, documents go to the IPFS node at `ipfs_url`.
+
+mod fs;
+mod ipfs;
+mod s3;
+
+pub use self::fs::FsStorage;
+pub use self::ipfs::{Cid, IpfsStorage};
+pub use self::s3::S3Storage;
+
+use std::{env, io, path::PathBuf};
+
+use crate::config::Profile;
+
+/// Content store for ciphertexts.
+pub trait Storage {
+    /// Stores `data`, returning the location it can be read back from.
+    fn put(&self, data: &[u8]) -> io::Result<String>;
+    /// Reads the data at `location`, `io::ErrorKind::NotFound` when there is none.
+    fn get(&self, location: &str) -> io::Result<Vec<u8>>;
+    fn exists(&self, location: &str) -> io::Result<bool>;
+    fn delete(&self, location: &str) -> io::Result<()>;
+}
+
+/// Storage backend as written in the configuration file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// IPFS HTTP API at the profile `ipfs_url`.
+    Ipfs {
+        /// Keep added documents pinned.
+        #[serde(default = "default_pin")]
+        pin: bool,
+        /// Version of the returned CIDs, 0 or 1.
+        #[serde(default)]
+        cid_version: u32,
+    },
+    /// Files named by their sha256 in a local directory.
+    Fs { path: PathBuf },
+    /// S3 compatible object store, objects are named by their sha256.
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        /// Defaults to `AWS_ACCESS_KEY_ID`.
+        access_key: Option<String>,
+        /// Defaults to `AWS_SECRET_ACCESS_KEY`.
+        secret_key: Option<String>,
+    },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Ipfs {
+            pin: default_pin(),
+            cid_version: 0,
+        }
+    }
+}
+
+fn default_pin() -> bool {
+    true
+}
+
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+/// Opens the storage backend of `profile`.
+pub fn open(profile: &Profile) -> io::Result<Box<dyn Storage>> {
+    Ok(match profile.storage {
+        StorageConfig::Ipfs { pin, cid_version } => {
+            Box::new(IpfsStorage::new(&profile.ipfs_url, pin, cid_version)?)
+        }
+        StorageConfig::Fs { ref path } => Box::new(FsStorage::new(path)),
+        StorageConfig::S3 {
+            ref endpoint,
+            ref bucket,
+            ref region,
+            ref access_key,
+            ref secret_key,
+        } => {
+            let access_key = credential(access_key, "AWS_ACCESS_KEY_ID")?;
+            let secret_key = credential(secret_key, "AWS_SECRET_ACCESS_KEY")?;
+            Box::new(S3Storage::new(
+                endpoint, bucket, region, access_key, secret_key,
+            )?)
+        }
+    })
+}
+
+/// IPFS node of `profile`, whichever backend stores its documents.
+pub fn ipfs(profile: &Profile) -> io::Result<IpfsStorage> {
+    match profile.storage {
+        StorageConfig::Ipfs { pin, cid_version } => {
+            IpfsStorage::new(&profile.ipfs_url, pin, cid_version)
+        }
+        _ => IpfsStorage::new(&profile.ipfs_url, default_pin(), 0),
+    }
+}
+
+fn credential(configured: &Option<String>, var: &str) -> io::Result<String> {
+    match configured {
+        Some(value) => Ok(value.clone()),
+        None => env::var(var).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("s3 storage needs {} or a configured key", var),
+            )
+        }),
+    }
+}
+
+/// Hex sha256 of `data`, used as the name of stored objects.
+fn content_name(data: &[u8]) -> String {
+    hex::encode(ring::digest::digest(&ring::digest::SHA256, data).as_ref())
+}
+
+/// Locations of the hashed backends are names returned by `put`, anything else could escape
+/// the storage root.
+fn check_content_name(location: &str) -> io::Result<()> {
+    if location.len() == 64 && location.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid storage location: {}", location),
+        ))
+    }
+}
+
+fn other_error<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
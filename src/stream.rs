@@ -0,0 +1,171 @@
+//! Chunked encryption of documents too large to be held in memory.
+//!
+//! Every chunk is encrypted on its own under the document key and stored as a separate object,
+//! so memory use is a small constant number of chunks, whatever the size of the document.
+
+use std::{
+    io::{self, Read, Write},
+    mem,
+};
+
+use ethereum_types::H256;
+use ring::digest;
+
+use crate::{
+    envelope::{check_plaintext_hash, Manifest},
+    helpers::{decrypt_chunk, encrypt_chunk, AEAD_OVERHEAD},
+    storage::Storage,
+};
+
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Encrypts `reader` chunk by chunk with `Cipher::Aes256Gcm`, returning the chunk locations and the plaintext size.
+///
+/// The next chunk is read ahead to tell `encrypt_chunk` whether the current one is the last.
+pub fn encrypt_chunks<R: Read>(
+    mut reader: R,
+    key: &[u8],
+    chunk_size: usize,
+    storage: &dyn Storage,
+) -> io::Result<(Vec<String>, u64)> {
+    let mut chunks = Vec::new();
+    let mut size = 0u64;
+    let mut buffer = vec![0u8; chunk_size];
+    let mut next = vec![0u8; chunk_size];
+    let mut len = read_chunk(&mut reader, &mut buffer)?;
+    while len > 0 {
+        let next_len = read_chunk(&mut reader, &mut next)?;
+        let index = chunks.len() as u64;
+        let encrypted = encrypt_chunk(key, buffer[..len].to_vec(), index, next_len == 0)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message))?;
+        chunks.push(storage.put(&encrypted)?);
+        size += len as u64;
+        mem::swap(&mut buffer, &mut next);
+        len = next_len;
+    }
+    Ok((chunks, size))
+}
+
+/// Decrypts the chunks of `manifest` into `writer`, returning the plaintext size.
+///
+/// The plaintext hash can only be checked once everything was written, a mismatch is an
+/// `io::ErrorKind::InvalidData` error wrapping `envelope::Error::PlaintextMismatch`.
+pub fn decrypt_chunks<W: Write>(
+    manifest: &Manifest,
+    key: &[u8],
+    storage: &dyn Storage,
+    mut writer: W,
+) -> io::Result<u64> {
+    let mut size = 0u64;
+    let mut hash = digest::Context::new(&digest::SHA256);
+    for (index, location) in manifest.chunks.iter().enumerate() {
+        let encrypted = storage.get(location)?;
+        if encrypted.len() > manifest.chunk_size as usize + AEAD_OVERHEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {} is larger than the chunk size", location),
+            ));
+        }
+        let last = index + 1 == manifest.chunks.len();
+        let chunk = decrypt_chunk(key, encrypted, index as u64, last).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {}: {}", location, e.message),
+            )
+        })?;
+        hash.update(&chunk);
+        writer.write_all(&chunk)?;
+        size += chunk.len() as u64;
+    }
+    writer.flush()?;
+    if size != manifest.header.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "decrypted {} bytes, document has {}",
+                size, manifest.header.size
+            ),
+        ));
+    }
+    let actual = H256::from_slice(hash.finish().as_ref());
+    check_plaintext_hash(manifest.header.plaintext_hash, actual)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(size)
+}
+
+/// Fills `buffer` unless the reader ends first.
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{plaintext_hash, Header};
+    use crate::storage::FsStorage;
+
+    #[test]
+    fn encrypt_and_decrypt_chunks() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FsStorage::new(root.path());
+        let key = H256::random().as_bytes().repeat(2);
+        let document: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+
+        let (chunks, size) = encrypt_chunks(&document[..], &key, 4096, &storage).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(size, document.len() as u64);
+
+        let mut manifest = Manifest {
+            header: Header {
+                threshold: 1,
+                dockey_id: H256::zero(),
+                servers_set_hash: H256::zero(),
+                size,
+                plaintext_hash: plaintext_hash(&document),
+                recipients: Vec::new(),
+            },
+            chunk_size: 4096,
+            chunks,
+        };
+        let mut decrypted = Vec::new();
+        decrypt_chunks(&manifest, &key, &storage, &mut decrypted).unwrap();
+        assert_eq!(decrypted, document);
+
+        let mut modified = manifest.clone();
+        let mut chunk = storage.get(&modified.chunks[1]).unwrap();
+        chunk[0] ^= 1;
+        modified.chunks[1] = storage.put(&chunk).unwrap();
+        let e = decrypt_chunks(&modified, &key, &storage, io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // chunks are bound to their position, whatever the manifest says
+        let mut reordered = manifest.clone();
+        reordered.chunks.swap(0, 1);
+        let mut written = Vec::new();
+        let e = decrypt_chunks(&reordered, &key, &storage, &mut written).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(written.is_empty());
+        let mut truncated = manifest.clone();
+        truncated.chunks.pop();
+        truncated.header.size = 8192;
+        truncated.header.plaintext_hash = plaintext_hash(&document[..8192]);
+        let e = decrypt_chunks(&truncated, &key, &storage, io::sink()).unwrap_err();
+        assert!(e.to_string().contains("chunk"));
+
+        manifest.header.plaintext_hash = H256::zero();
+        let e = decrypt_chunks(&manifest, &key, &storage, io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        manifest.chunks.pop();
+        assert!(decrypt_chunks(&manifest, &key, &storage, io::sink()).is_err());
+    }
+}
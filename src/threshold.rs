@@ -0,0 +1,252 @@
+//! Server half of the Secret Store threshold cryptography.
+//!
+//! A joint secret is shared between nodes with a random polynomial of degree `threshold`,
+//! each node keeping the polynomial value at its own id. Any `threshold + 1` shares restore
+//! the secret through lagrange interpolation at zero, fewer shares reveal nothing about it.
+
+use crate::bytes::Bytes;
+use crate::document_key::DecryptedDocumentKey;
+use crate::errors;
+use ethkey::{self, math, Generator, KeyPair, Public, Random, Secret};
+use jsonrpc_core::Error;
+use parity_crypto as crypto;
+
+/// Secret share of a single node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyShare {
+    pub id: Secret,
+    pub share: Secret,
+}
+
+/// Joint key split between nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointKey {
+    pub threshold: usize,
+    pub public: Public,
+    pub shares: Vec<KeyShare>,
+}
+
+pub fn generate_joint_key(threshold: usize, nodes: usize) -> Result<JointKey, Error> {
+    if threshold >= nodes {
+        return Err(errors::invalid_params(
+            "threshold",
+            format!("{} nodes cannot serve threshold {}", nodes, threshold),
+        ));
+    }
+    let polynom = (0..=threshold)
+        .map(|_| random_secret())
+        .collect::<Result<Vec<_>, _>>()?;
+    let public = *KeyPair::from_secret(polynom[0].clone())
+        .map_err(errors::encryption)?
+        .public();
+
+    let mut shares = Vec::with_capacity(nodes);
+    for _ in 0..nodes {
+        let id = random_secret()?;
+        // horner's scheme
+        let mut share = polynom[threshold].clone();
+        for coefficient in polynom.iter().rev().skip(1) {
+            share.mul(&id).map_err(errors::encryption)?;
+            share.add(coefficient).map_err(errors::encryption)?;
+        }
+        shares.push(KeyShare { id, share });
+    }
+    Ok(JointKey {
+        threshold,
+        public,
+        shares,
+    })
+}
+
+/// Lagrange coefficient of `id` for interpolation at zero over `id` and `other_ids`.
+pub fn lagrange_coefficient<'a, I>(id: &Secret, other_ids: I) -> Result<Secret, Error>
+where
+    I: IntoIterator<Item = &'a Secret>,
+{
+    let mut coefficient: Option<Secret> = None;
+    for other_id in other_ids {
+        let mut denominator = other_id.clone();
+        denominator.sub(id).map_err(errors::encryption)?;
+        denominator.inv().map_err(errors::encryption)?;
+        denominator.mul(other_id).map_err(errors::encryption)?;
+        coefficient = Some(match coefficient {
+            None => denominator,
+            Some(mut coefficient) => {
+                coefficient.mul(&denominator).map_err(errors::encryption)?;
+                coefficient
+            }
+        });
+    }
+    coefficient.ok_or_else(|| errors::invalid_params("other_ids", "no other nodes"))
+}
+
+/// Share of every node multiplied by its lagrange coefficient, these sum up to the joint secret.
+pub fn node_shadows(shares: &[KeyShare]) -> Result<Vec<Secret>, Error> {
+    if shares.len() == 1 {
+        return Ok(vec![shares[0].share.clone()]);
+    }
+    let mut shadows = Vec::with_capacity(shares.len());
+    for (i, share) in shares.iter().enumerate() {
+        let other_ids = shares
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| i != j)
+            .map(|(_, other)| &other.id);
+        let mut shadow = lagrange_coefficient(&share.id, other_ids)?;
+        shadow.mul(&share.share).map_err(errors::encryption)?;
+        shadows.push(shadow);
+    }
+    Ok(shadows)
+}
+
+/// Restores the joint secret from any `threshold + 1` shares.
+pub fn joint_secret(shares: &[KeyShare]) -> Result<Secret, Error> {
+    let shadows = node_shadows(shares)?;
+    let mut secret = shadows
+        .first()
+        .cloned()
+        .ok_or_else(|| errors::invalid_params("shares", "no shares"))?;
+    for shadow in shadows.iter().skip(1) {
+        secret.add(shadow).map_err(errors::encryption)?;
+    }
+    Ok(secret)
+}
+
+/// Restores the document key stored as `(common_point, encrypted_point)` from any
+/// `threshold + 1` shares.
+pub fn decrypt_document_key(
+    common_point: &Public,
+    encrypted_point: &Public,
+    shares: &[KeyShare],
+) -> Result<Public, Error> {
+    let mut joint_shadow_point = *common_point;
+    math::public_mul_secret(&mut joint_shadow_point, &joint_secret(shares)?)
+        .map_err(errors::encryption)?;
+    let mut document_key = *encrypted_point;
+    math::public_sub(&mut document_key, &joint_shadow_point).map_err(errors::encryption)?;
+    Ok(document_key)
+}
+
+/// Shadow decryption of a stored document key by `threshold + 1` shares.
+///
+/// Every node hides `common_point * node_shadow` behind a random factor and reveals only the
+/// remainder to the requester as a shadow coefficient encrypted with `requester`. The result
+/// is what `decrypt_with_shadow_coefficients` expects.
+pub fn decrypt_shadows(
+    requester: &Public,
+    common_point: &Public,
+    encrypted_point: &Public,
+    shares: &[KeyShare],
+) -> Result<DecryptedDocumentKey, Error> {
+    let node_shadows = node_shadows(shares)?;
+    let mut decrypted_secret = *encrypted_point;
+    let mut decrypt_shadows = Vec::with_capacity(node_shadows.len());
+    for node_shadow in node_shadows {
+        let factor = random_secret()?;
+        let mut shadow_key = node_shadow.clone();
+        shadow_key.mul(&factor).map_err(errors::encryption)?;
+        let mut shadow_point = *common_point;
+        math::public_mul_secret(&mut shadow_point, &shadow_key).map_err(errors::encryption)?;
+        math::public_sub(&mut decrypted_secret, &shadow_point).map_err(errors::encryption)?;
+
+        let mut coefficient = factor;
+        coefficient.dec().map_err(errors::encryption)?;
+        coefficient.mul(&node_shadow).map_err(errors::encryption)?;
+        let encrypted_coefficient =
+            ethkey::crypto::ecies::encrypt(requester, &crypto::DEFAULT_MAC, coefficient.as_bytes())
+                .map_err(errors::encryption)?;
+        decrypt_shadows.push(Bytes::new(encrypted_coefficient));
+    }
+    Ok(DecryptedDocumentKey {
+        decrypted_secret: decrypted_secret.into(),
+        common_point: (*common_point).into(),
+        decrypt_shadows,
+    })
+}
+
+fn random_secret() -> Result<Secret, Error> {
+    Random
+        .generate()
+        .map(|key_pair| key_pair.secret().clone())
+        .map_err(errors::encryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{decrypt_document_with_shadow, encrypt_document, generate_document_key};
+
+    #[test]
+    fn any_threshold_plus_one_shares_restore_secret() {
+        let key = generate_joint_key(2, 5).unwrap();
+        let subsets: [&[usize]; 4] = [&[0, 1, 2], &[2, 3, 4], &[0, 2, 4], &[0, 1, 2, 3, 4]];
+        for subset in subsets.iter() {
+            let shares: Vec<_> = subset.iter().map(|&i| key.shares[i].clone()).collect();
+            let secret = joint_secret(&shares).unwrap();
+            assert_eq!(*KeyPair::from_secret(secret).unwrap().public(), key.public);
+        }
+
+        let shares = &key.shares[..2];
+        let secret = joint_secret(shares).unwrap();
+        assert!(*KeyPair::from_secret(secret).unwrap().public() != key.public);
+    }
+
+    #[test]
+    fn threshold_must_be_less_than_nodes() {
+        assert!(generate_joint_key(3, 3).is_err());
+        assert_eq!(generate_joint_key(0, 1).unwrap().shares.len(), 1);
+    }
+
+    #[test]
+    fn decrypt_shadows_round_trip() {
+        let requester = Random.generate().unwrap();
+        let key = generate_joint_key(1, 3).unwrap();
+        let document_key = generate_document_key(*requester.public(), key.public).unwrap();
+        let plain_key = ethkey::crypto::ecies::decrypt(
+            requester.secret(),
+            &crypto::DEFAULT_MAC,
+            &document_key.encrypted_key.0,
+        )
+        .unwrap();
+        let document = b"Hello, world!".to_vec();
+        let encrypted_document = encrypt_document(&plain_key, document.clone()).unwrap();
+
+        let common_point = document_key.common_point.into();
+        let encrypted_point = document_key.encrypted_point.into();
+        assert_eq!(
+            decrypt_document_key(&common_point, &encrypted_point, &key.shares[1..])
+                .unwrap()
+                .as_bytes(),
+            &plain_key[..]
+        );
+
+        let decrypted = decrypt_shadows(
+            requester.public(),
+            &common_point,
+            &encrypted_point,
+            &key.shares[..2],
+        )
+        .unwrap();
+        let shadows = decrypted
+            .decrypt_shadows
+            .iter()
+            .map(|shadow| {
+                let shadow = ethkey::crypto::ecies::decrypt(
+                    requester.secret(),
+                    &crypto::DEFAULT_MAC,
+                    &shadow.0,
+                )
+                .unwrap();
+                Secret::from_unsafe_slice(&shadow).unwrap()
+            })
+            .collect();
+        let decrypted_document = decrypt_document_with_shadow(
+            decrypted.decrypted_secret.into(),
+            decrypted.common_point.into(),
+            shadows,
+            encrypted_document,
+        )
+        .unwrap();
+        assert_eq!(decrypted_document, document);
+    }
+}
@@ -1,21 +1,25 @@
 use std::io::{self, BufReader, Read, Write};
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
 
 use ethereum_types::{H160, H256};
 use ethkey::Password;
 use ring::digest::{Context, Digest, SHA256};
 
+const STDIN_ERROR: &str = "Unable to ask for password on non-interactive terminal.";
+
+/// Password of a new account, asked twice. Prompts go to stderr, stdout may be a decrypted
+/// document.
 pub fn password_prompt() -> Result<Password, String> {
     use rpassword::read_password;
-    const STDIN_ERROR: &'static str = "Unable to ask for password on non-interactive terminal.";
-    println!("Please note that password is NOT RECOVERABLE.");
-    print!("Type password: ");
-    flush_stdout();
+    eprintln!("Please note that password is NOT RECOVERABLE.");
+    eprint!("Type password: ");
 
     let password = read_password().map_err(|_| STDIN_ERROR.to_owned())?.into();
 
-    print!("Repeat password: ");
-    flush_stdout();
+    eprint!("Repeat password: ");
 
     let password_repeat = read_password().map_err(|_| STDIN_ERROR.to_owned())?.into();
 
@@ -25,27 +29,13 @@ pub fn password_prompt() -> Result<Password, String> {
     Ok(password)
 }
 
-pub fn accounts_prompt(accounts: Vec<H160>) -> u32 {
-    let mut input = String::new();
-    println!("Account lists: \n");
-    for i in 0..accounts.len() {
-        println!("{}: {:?}", i, accounts[0]);
-    }
-    println!("\n");
-    loop {
-        println!("Select an account: ");
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
-                let input_u32: u32 = match input.trim().parse() {
-                    Ok(n) => n,
-                    Err(_) => continue,
-                };
-                // something wrokng on break statement when you pick non-number
-                break input_u32;
-            }
-            Err(error) => println!("error: {}", error),
-        }
-    }
+/// Password of an existing account, asked once.
+pub fn unlock_prompt(account: &H160) -> Result<Password, String> {
+    use rpassword::read_password;
+    eprint!("Password of {:?}: ", account);
+    read_password()
+        .map(Password::from)
+        .map_err(|_| STDIN_ERROR.to_owned())
 }
 
 pub fn create_dockey_id(file: PathBuf) -> io::Result<H256> {
@@ -55,8 +45,68 @@ pub fn create_dockey_id(file: PathBuf) -> io::Result<H256> {
     Ok(message)
 }
 
-fn flush_stdout() {
-    io::stdout().flush().expect("stdout is flushable; qed");
+/// File written under a temporary name next to its path and renamed over it on `commit`, so a
+/// failed write never leaves a partial file behind. Only the owner can read it.
+pub struct AtomicFile {
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file path", path.display()),
+            )
+        })?;
+        let temp_path = path.with_file_name(format!(
+            ".{}.{:016x}.tmp",
+            name.to_string_lossy(),
+            rand::random::<u64>()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        Ok(AtomicFile {
+            file: options.open(&temp_path)?,
+            temp_path,
+            path: path.into(),
+            committed: false,
+        })
+    }
+
+    /// Replaces the file at the path with what was written.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
 }
 
 fn sha256_digest<R: Read>(mut reader: R) -> io::Result<Digest> {
@@ -71,3 +121,34 @@ fn sha256_digest<R: Read>(mut reader: R) -> io::Result<Digest> {
     }
     Ok(context.finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_file_replaces_on_commit_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("document.bin");
+        fs::write(&path, b"old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(&[0xff, 0x00, 0xfe]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(&[0xff, 0x00, 0xfe]).unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [0xff, 0x00, 0xfe]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}